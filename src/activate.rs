@@ -0,0 +1,121 @@
+#![deny(warnings)]
+
+use std::fmt::Write;
+
+use crate::environment::Environment;
+use crate::options::Shell;
+
+/// The function that undoes the statements printed by `print`.
+const DEACTIVATE: &str = "rpy_deactivate";
+
+/// The prefix of the shell variables holding the values `DEACTIVATE` restores.
+const SAVED: &str = "_RPY_OLD_";
+
+/// Prints statements that make `environment`'s changes in `shell`, for `eval "$(rpy --rpy-env)"`
+/// or an `.envrc`. The values the variables had before are kept in shell (not environment)
+/// variables, and restored by an `rpy_deactivate` function.
+pub fn print(environment: &Environment, shell: Shell) {
+    print!("{}", statements(environment, shell));
+}
+
+fn statements(environment: &Environment, shell: Shell) -> String {
+    // Variables rpy removes only to keep its own settings from reaching python: an interactive
+    // shell wants to keep them, for running rpy itself.
+    let changes = environment
+        .changes()
+        .filter(|(name, _)| !name.starts_with("RPY_"))
+        .map(|(name, value)| (name, value.map(|v| v.to_string_lossy())))
+        .collect::<Vec<_>>();
+    let mut out = String::new();
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            for (name, value) in &changes {
+                let _ = writeln!(
+                    out,
+                    "if [ -n \"${{{name}+x}}\" ]; then {SAVED}{name}=\"${name}\"; \
+                     else unset {SAVED}{name}; fi"
+                );
+                match value {
+                    Some(value) => {
+                        let _ = writeln!(out, "export {name}={}", quote_posix(value));
+                    }
+                    None => {
+                        let _ = writeln!(out, "unset {name}");
+                    }
+                }
+            }
+            let _ = writeln!(out, "{DEACTIVATE}() {{");
+            for (name, _) in &changes {
+                let _ = writeln!(
+                    out,
+                    "    if [ -n \"${{{SAVED}{name}+x}}\" ]; then export {name}=\"${SAVED}{name}\"; \
+                     unset {SAVED}{name}; else unset {name}; fi"
+                );
+            }
+            let _ = writeln!(out, "    unset -f {DEACTIVATE}");
+            let _ = writeln!(out, "}}");
+        }
+        Shell::Fish => {
+            for (name, value) in &changes {
+                let _ = writeln!(
+                    out,
+                    "if set -q {name}; set -g {SAVED}{name} ${name}; else; set -e {SAVED}{name}; end"
+                );
+                match value {
+                    Some(value) => {
+                        let _ = writeln!(out, "set -gx {name} {}", quote_fish(value));
+                    }
+                    None => {
+                        let _ = writeln!(out, "set -e {name}");
+                    }
+                }
+            }
+            let _ = writeln!(out, "function {DEACTIVATE}");
+            for (name, _) in &changes {
+                let _ = writeln!(
+                    out,
+                    "    if set -q {SAVED}{name}; set -gx {name} ${SAVED}{name}; \
+                     set -e {SAVED}{name}; else; set -e {name}; end"
+                );
+            }
+            let _ = writeln!(out, "    functions -e {DEACTIVATE}");
+            let _ = writeln!(out, "end");
+        }
+    }
+    out
+}
+
+fn quote_posix(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_fish, quote_posix, statements};
+    use crate::environment::Environment;
+    use crate::options::Shell;
+
+    #[test]
+    fn should_quote_values() {
+        assert_eq!(quote_posix("/a b/c"), "'/a b/c'");
+        assert_eq!(quote_posix("it's"), "'it'\\''s'");
+        assert_eq!(quote_fish("it's a \\"), "'it\\'s a \\\\'");
+    }
+
+    #[test]
+    fn should_leave_rpy_variables_alone() {
+        let mut environment = Environment::default();
+        environment.set("PYTHONSAFEPATH", "1");
+        environment.remove("RPY_PROFILE");
+        let bash = statements(&environment, Shell::Bash);
+        assert!(bash.contains("\nexport PYTHONSAFEPATH='1'\n"), "{bash}");
+        assert!(!bash.contains("RPY_PROFILE"), "{bash}");
+        let fish = statements(&environment, Shell::Fish);
+        assert!(fish.contains("\nset -gx PYTHONSAFEPATH '1'\n"), "{fish}");
+        assert!(!fish.contains("RPY_PROFILE"), "{fish}");
+    }
+}
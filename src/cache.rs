@@ -0,0 +1,154 @@
+#![deny(warnings)]
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+/// The directory rpy keeps its cache in: `$XDG_CACHE_HOME/rpy`, or `~/.cache/rpy`.
+pub fn dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|cache| cache.join("rpy"))
+}
+
+/// What rpy has learned about an interpreter by running it, along with what identifies the
+/// interpreter binary it was learned from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InterpreterInfo {
+    pub path: String,
+    pub inode: u64,
+    pub mtime_ns: i64,
+    pub version: String,
+    pub prefix: String,
+    pub platform: String,
+}
+
+/// The facts about an interpreter that have to be found by running it.
+pub struct QueryResult {
+    pub version: String,
+    pub prefix: String,
+    pub platform: String,
+}
+
+/// 64-bit FNV-1a which, unlike `DefaultHasher`, is stable between releases so is safe to persist.
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A name for the cache entry of `key`.
+pub fn entry_name(key: &str) -> String {
+    let mut hasher = Fnv::default();
+    hasher.write(key.as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+fn interpreters_dir() -> Option<PathBuf> {
+    dir().map(|dir| dir.join("interpreters"))
+}
+
+/// Returns the information about `interpreter` (an absolute path), using `query` to find it if it
+/// is not cached or the interpreter binary has changed since it was. Entries are keyed on the path
+/// as given rather than where it links to: a virtualenv's `bin/python` is a symlink to the base
+/// interpreter, but has a different `sys.prefix`. Failures to read or write the cache are not
+/// errors, the interpreter is just queried again.
+pub fn interpreter_info(
+    interpreter: &Path,
+    query: impl FnOnce() -> Result<QueryResult>,
+) -> Result<InterpreterInfo> {
+    let path = interpreter.to_string_lossy().to_string();
+    let metadata =
+        fs::metadata(interpreter).wrap_err(format!("Unable to stat {}", interpreter.display()))?;
+    let inode = metadata.ino();
+    let mtime_ns = metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec();
+    let entry = interpreters_dir().map(|dir| dir.join(entry_name(&path) + ".toml"));
+    if let Some(entry) = &entry
+        && let Ok(contents) = fs::read_to_string(entry)
+        && let Ok(info) = toml::from_str::<InterpreterInfo>(&contents)
+        && info.path == path
+        && info.inode == inode
+        && info.mtime_ns == mtime_ns
+    {
+        return Ok(info);
+    }
+    let QueryResult {
+        version,
+        prefix,
+        platform,
+    } = query()?;
+    let info = InterpreterInfo {
+        path,
+        inode,
+        mtime_ns,
+        version,
+        prefix,
+        platform,
+    };
+    if let Some(entry) = &entry {
+        write(entry, &toml::to_string(&info)?).ok();
+    }
+    Ok(info)
+}
+
+/// Every readable interpreter entry in the cache.
+pub fn interpreter_infos() -> Result<Vec<InterpreterInfo>> {
+    let Some(dir) = interpreters_dir() else {
+        return Ok(vec![]);
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).wrap_err(format!("Unable to read {}", dir.display())),
+    };
+    let mut infos = entries
+        .filter_map(|entry| fs::read_to_string(entry.ok()?.path()).ok())
+        .filter_map(|contents| toml::from_str::<InterpreterInfo>(&contents).ok())
+        .collect::<Vec<_>>();
+    infos.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(infos)
+}
+
+/// Removes everything rpy has cached.
+pub fn clear() -> Result<Option<PathBuf>> {
+    let Some(dir) = dir() else {
+        return Ok(None);
+    };
+    match fs::remove_dir_all(&dir) {
+        Ok(()) => Ok(Some(dir)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Some(dir)),
+        Err(err) => Err(err).wrap_err(format!("Unable to remove {}", dir.display())),
+    }
+}
+
+/// Writes `contents` to `path` via a temporary file, so concurrent readers never see a partial
+/// entry.
+pub fn write(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
@@ -0,0 +1,758 @@
+#![deny(warnings)]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{ContextCompat, Result, WrapErr, eyre};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    tool: Tool,
+    project: Option<Project>,
+    /// The dependencies declared in a script's inline metadata.
+    #[serde(skip)]
+    dependencies: Vec<String>,
+}
+
+/// PEP 723 inline script metadata, whose `[tool.rpy]` table is optional.
+#[derive(Deserialize, Debug)]
+struct ScriptMetadata {
+    #[serde(rename = "requires-python")]
+    requires_python: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    #[serde(default)]
+    tool: OptionalTool,
+}
+
+/// A `pyproject.toml` used alongside an `rpy.toml` or `.rpy.toml`, which needn't have a
+/// `[tool.rpy]` table.
+#[derive(Deserialize, Debug)]
+struct PyProject {
+    #[serde(default)]
+    tool: OptionalTool,
+    project: Option<Project>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct OptionalTool {
+    #[serde(default)]
+    rpy: PyConfig,
+}
+
+/// The parts of the standard `[project]` table rpy cares about.
+#[derive(Deserialize, Debug)]
+struct Project {
+    #[serde(rename = "requires-python")]
+    requires_python: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Tool {
+    rpy: PyConfig,
+}
+
+/// The `[tool.rpy]` table.
+#[derive(Deserialize, Debug, Default)]
+pub struct PyConfig {
+    #[serde(flatten)]
+    settings: Settings,
+    #[serde(default)]
+    profiles: BTreeMap<String, Settings>,
+    #[serde(default, rename = "override")]
+    overrides: Vec<Override>,
+}
+
+impl PyConfig {
+    /// Layers another file's `[tool.rpy]` over this one: its settings take precedence, profiles
+    /// of the same name are layered, and its overrides apply after these.
+    fn layer(mut self, over: PyConfig) -> PyConfig {
+        for (name, settings) in over.profiles {
+            let profile = match self.profiles.remove(&name) {
+                Some(base) => base.layer(settings),
+                None => settings,
+            };
+            self.profiles.insert(name, profile);
+        }
+        self.overrides.extend(over.overrides);
+        PyConfig {
+            settings: self.settings.layer(over.settings),
+            profiles: self.profiles,
+            overrides: self.overrides,
+        }
+    }
+}
+
+/// A `[[tool.rpy.override]]` entry, applying its settings to matching scripts or modules.
+#[derive(Deserialize, Debug)]
+struct Override {
+    script: Option<OneOrMany<String>>,
+    module: Option<OneOrMany<String>>,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+/// What is being run, for the purposes of matching overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryPoint<'a> {
+    /// A script, relative to the project root.
+    Script(&'a Path),
+    Module(&'a str),
+    Other,
+}
+
+impl Override {
+    fn matches(&self, entry_point: EntryPoint) -> Result<bool> {
+        if self.script.is_none() && self.module.is_none() {
+            return Err(eyre!(
+                "Each [[tool.rpy.override]] must have a 'script' or 'module' to match"
+            ));
+        }
+        match entry_point {
+            EntryPoint::Script(script) => {
+                let options = MatchOptions {
+                    require_literal_separator: true,
+                    ..MatchOptions::default()
+                };
+                for glob in self.script.iter().flat_map(OneOrMany::iter) {
+                    let pattern = Pattern::new(glob)
+                        .wrap_err(format!("Invalid override script glob '{glob}'"))?;
+                    if pattern.matches_path_with(script, options) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            EntryPoint::Module(module) => {
+                Ok(self.module.iter().flat_map(OneOrMany::iter).any(|prefix| {
+                    module
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+                }))
+            }
+            EntryPoint::Other => Ok(false),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        match self {
+            OneOrMany::One(item) => std::slice::from_ref(item).iter(),
+            OneOrMany::Many(items) => items.iter(),
+        }
+    }
+}
+
+/// `pre_run` or `post_run`: either a single command, or a list of named steps. Steps are tried
+/// first so that an empty array means no steps (e.g. a profile switching off a base `pre_run`)
+/// rather than a command with an empty argv.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PreRun {
+    Steps(Vec<PreRunStep>),
+    Command(CommandLine),
+}
+
+/// A pre_run command: a string is given to `pre_run_shell`, an array is run directly as an argv.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+impl fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandLine::Shell(cmd) => write!(f, "{cmd}"),
+            CommandLine::Argv(argv) => write!(f, "{}", argv.join(" ")),
+        }
+    }
+}
+
+/// One of a list of `pre_run` or `post_run` steps. `cwd` is relative to the project root, and
+/// `inputs` default to `pre_run_inputs` (post_run steps always run, so ignore them).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PreRunStep {
+    pub name: Option<String>,
+    pub cmd: CommandLine,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub inputs: Option<Vec<String>>,
+}
+
+/// `[tool.rpy.env_manager]`: a virtual environment rpy creates and installs a lockfile into.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EnvManager {
+    /// The requirements file to install into the environment, relative to the project root.
+    pub lockfile: String,
+    /// Where the environment lives, relative to the project root. Defaults to `.venv`.
+    pub env_dir: Option<String>,
+    /// What to manage it with. Defaults to `uv` if it is on the `PATH`, otherwise `pip`.
+    pub tool: Option<EnvTool>,
+    /// The interpreter to create the environment from.
+    pub python: Option<String>,
+    /// A directory of wheels to install from instead of the package index.
+    pub wheelhouse: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvTool {
+    Uv,
+    Pip,
+}
+
+/// Everything that can be set in `[tool.rpy]`, and layered over it by a profile.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub interpreter: Option<OneOrMany<String>>,
+    pub bin_path: Option<String>,
+    pub source_root: Option<OneOrMany<String>>,
+    pub inherit_pythonpath: Option<bool>,
+    pub no_user_site: Option<bool>,
+    pub safe_path: Option<bool>,
+    pub pre_run: Option<PreRun>,
+    pub pre_run_shell: Option<Vec<String>>,
+    pub pre_run_inputs: Option<Vec<String>>,
+    pub pre_run_lock_timeout: Option<u64>,
+    pub pre_run_capture_lines: Option<usize>,
+    pub supervise: Option<bool>,
+    pub post_run: Option<PreRun>,
+    pub telemetry_log: Option<String>,
+    pub env_manager: Option<EnvManager>,
+    pub script_env: Option<bool>,
+    pub dotenv: Option<OneOrMany<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub env_remove: Option<Vec<String>>,
+    pub env_prepend: Option<BTreeMap<String, OneOrMany<String>>>,
+    pub env_append: Option<BTreeMap<String, OneOrMany<String>>>,
+}
+
+/// Merges two optional maps, with entries in `over` replacing those with the same key in `base`.
+fn layer_map<V>(
+    base: Option<BTreeMap<String, V>>,
+    over: Option<BTreeMap<String, V>>,
+) -> Option<BTreeMap<String, V>> {
+    match (base, over) {
+        (Some(mut base), Some(over)) => {
+            base.extend(over);
+            Some(base)
+        }
+        (base, over) => over.or(base),
+    }
+}
+
+impl Settings {
+    /// Returns these settings with anything set in `over` taking precedence.
+    pub fn layer(self, over: Settings) -> Settings {
+        Settings {
+            interpreter: over.interpreter.or(self.interpreter),
+            bin_path: over.bin_path.or(self.bin_path),
+            source_root: over.source_root.or(self.source_root),
+            inherit_pythonpath: over.inherit_pythonpath.or(self.inherit_pythonpath),
+            no_user_site: over.no_user_site.or(self.no_user_site),
+            safe_path: over.safe_path.or(self.safe_path),
+            pre_run: over.pre_run.or(self.pre_run),
+            pre_run_shell: over.pre_run_shell.or(self.pre_run_shell),
+            pre_run_inputs: over.pre_run_inputs.or(self.pre_run_inputs),
+            pre_run_lock_timeout: over.pre_run_lock_timeout.or(self.pre_run_lock_timeout),
+            pre_run_capture_lines: over.pre_run_capture_lines.or(self.pre_run_capture_lines),
+            supervise: over.supervise.or(self.supervise),
+            post_run: over.post_run.or(self.post_run),
+            telemetry_log: over.telemetry_log.or(self.telemetry_log),
+            env_manager: over.env_manager.or(self.env_manager),
+            script_env: over.script_env.or(self.script_env),
+            dotenv: over.dotenv.or(self.dotenv),
+            env: layer_map(self.env, over.env),
+            env_remove: match (self.env_remove, over.env_remove) {
+                (Some(mut base), Some(over)) => {
+                    base.extend(over);
+                    Some(base)
+                }
+                (base, over) => over.or(base),
+            },
+            env_prepend: layer_map(self.env_prepend, over.env_prepend),
+            env_append: layer_map(self.env_append, over.env_append),
+        }
+    }
+}
+
+impl Config {
+    /// Loads and layers the configuration `files`, which are `pyproject.toml`, `rpy.toml` and
+    /// `.rpy.toml` in increasing priority. `[tool.rpy]` is only required in a `pyproject.toml`
+    /// when there is nothing else.
+    pub fn load(files: &[PathBuf]) -> Result<Config> {
+        let mut config: Option<Config> = None;
+        for file in files {
+            let toml_doc =
+                fs::read_to_string(file).wrap_err(format!("Unable to read {}", file.display()))?;
+            let layer = if file
+                .file_name()
+                .is_some_and(|name| name == "pyproject.toml")
+            {
+                if files.len() == 1 {
+                    Self::parse(&toml_doc)?
+                } else {
+                    let pyproject: PyProject = toml::from_str(&toml_doc)
+                        .wrap_err(format!("Unable to parse {}", file.display()))?;
+                    Config {
+                        tool: Tool {
+                            rpy: pyproject.tool.rpy,
+                        },
+                        project: pyproject.project,
+                        dependencies: vec![],
+                    }
+                }
+            } else {
+                Config {
+                    tool: Tool {
+                        rpy: toml::from_str(&toml_doc)
+                            .wrap_err(format!("Unable to parse {}", file.display()))?,
+                    },
+                    project: None,
+                    dependencies: vec![],
+                }
+            };
+            config = Some(match config {
+                Some(base) => Config {
+                    tool: Tool {
+                        rpy: base.tool.rpy.layer(layer.tool.rpy),
+                    },
+                    project: base.project.or(layer.project),
+                    dependencies: vec![],
+                },
+                None => layer,
+            });
+        }
+        config.wrap_err("No configuration files to load")
+    }
+
+    fn parse(toml_doc: &str) -> Result<Config> {
+        toml::from_str(toml_doc)
+            .wrap_err("Unable to read toml document or find the rpy.tool configuration in it")
+    }
+
+    /// Reads the configuration from the TOML of a script's PEP 723 `# /// script` block.
+    pub fn from_script_metadata(metadata: &str) -> Result<Config> {
+        let metadata: ScriptMetadata =
+            toml::from_str(metadata).wrap_err("Unable to parse the inline script metadata")?;
+        Ok(Config {
+            tool: Tool {
+                rpy: metadata.tool.rpy,
+            },
+            project: Some(Project {
+                requires_python: metadata.requires_python,
+            }),
+            dependencies: metadata.dependencies,
+        })
+    }
+
+    pub fn dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
+
+    pub fn requires_python(&self) -> Option<&str> {
+        self.project.as_ref()?.requires_python.as_deref()
+    }
+
+    /// Resolves the settings to use: the base table, then any overrides matching the entry point
+    /// in the order they are declared, then the named profile (if any).
+    pub fn resolve(self, entry_point: EntryPoint, profile: Option<&str>) -> Result<Settings> {
+        let PyConfig {
+            mut settings,
+            mut profiles,
+            overrides,
+        } = self.tool.rpy;
+        for over in overrides {
+            if over.matches(entry_point)? {
+                settings = settings.layer(over.settings);
+            }
+        }
+        let Some(name) = profile else {
+            return Ok(settings);
+        };
+        let profile = profiles.remove(name).wrap_err_with(|| {
+            if profiles.is_empty() {
+                format!("Unknown profile '{name}': no [tool.rpy.profiles] are defined")
+            } else {
+                let available = profiles.keys().cloned().collect::<Vec<_>>();
+                format!(
+                    "Unknown profile '{name}' (available: {})",
+                    available.join(", ")
+                )
+            }
+        })?;
+        Ok(settings.layer(profile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use super::{
+        CommandLine, Config, EntryPoint, OneOrMany, PreRun, PreRunStep, PyConfig, Settings, Tool,
+    };
+
+    const PROFILES: &str = r#"
+[tool.rpy]
+interpreter = 'out/env/bin/python'
+source_root = 'src/py'
+
+[tool.rpy.profiles.debug]
+interpreter = 'out/debug/bin/python'
+
+[tool.rpy.profiles.py312]
+interpreter = 'out/py312/bin/python'
+source_root = ['src/py', 'compat/py312']
+"#;
+
+    #[test]
+    fn should_use_base_settings_without_a_profile() {
+        assert_eq!(
+            Config::parse(PROFILES)
+                .unwrap()
+                .resolve(EntryPoint::Other, None)
+                .unwrap(),
+            Settings {
+                interpreter: Some(OneOrMany::One("out/env/bin/python".into())),
+                source_root: Some(OneOrMany::One("src/py".into())),
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn should_layer_profile_over_base_settings() {
+        assert_eq!(
+            Config::parse(PROFILES)
+                .unwrap()
+                .resolve(EntryPoint::Other, Some("debug"))
+                .unwrap(),
+            Settings {
+                interpreter: Some(OneOrMany::One("out/debug/bin/python".into())),
+                source_root: Some(OneOrMany::One("src/py".into())),
+                ..Settings::default()
+            }
+        );
+        assert_eq!(
+            Config::parse(PROFILES)
+                .unwrap()
+                .resolve(EntryPoint::Other, Some("py312"))
+                .unwrap(),
+            Settings {
+                interpreter: Some(OneOrMany::One("out/py312/bin/python".into())),
+                source_root: Some(OneOrMany::Many(vec![
+                    "src/py".into(),
+                    "compat/py312".into()
+                ])),
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn should_list_available_profiles_when_unknown() {
+        let err = Config::parse(PROFILES)
+            .unwrap()
+            .resolve(EntryPoint::Other, Some("prod"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile 'prod' (available: debug, py312)"
+        );
+    }
+
+    const OVERRIDES: &str = r#"
+[tool.rpy]
+interpreter = 'out/env/bin/python'
+source_root = 'src/py'
+
+[tool.rpy.profiles.debug]
+interpreter = 'out/debug/bin/python'
+
+[[tool.rpy.override]]
+script = 'tools/**/*.py'
+module = 'tools'
+interpreter = 'out/tools/bin/python'
+
+[[tool.rpy.override]]
+script = ['tools/legacy/*.py', 'scripts/*.py']
+source_root = 'legacy'
+"#;
+
+    fn resolve_overrides(entry_point: EntryPoint, profile: Option<&str>) -> Settings {
+        Config::parse(OVERRIDES)
+            .unwrap()
+            .resolve(entry_point, profile)
+            .unwrap()
+    }
+
+    #[test]
+    fn should_apply_matching_script_overrides_in_order() {
+        assert_eq!(
+            resolve_overrides(EntryPoint::Script(Path::new("tools/build.py")), None),
+            Settings {
+                interpreter: Some(OneOrMany::One("out/tools/bin/python".into())),
+                source_root: Some(OneOrMany::One("src/py".into())),
+                ..Settings::default()
+            }
+        );
+        assert_eq!(
+            resolve_overrides(EntryPoint::Script(Path::new("tools/legacy/old.py")), None),
+            Settings {
+                interpreter: Some(OneOrMany::One("out/tools/bin/python".into())),
+                source_root: Some(OneOrMany::One("legacy".into())),
+                ..Settings::default()
+            }
+        );
+        assert_eq!(
+            resolve_overrides(EntryPoint::Script(Path::new("scripts/sub/deep.py")), None),
+            Config::parse(OVERRIDES)
+                .unwrap()
+                .resolve(EntryPoint::Other, None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_match_module_prefixes_on_dots() {
+        assert_eq!(
+            resolve_overrides(EntryPoint::Module("tools"), None).interpreter,
+            Some(OneOrMany::One("out/tools/bin/python".into()))
+        );
+        assert_eq!(
+            resolve_overrides(EntryPoint::Module("tools.build"), None).interpreter,
+            Some(OneOrMany::One("out/tools/bin/python".into()))
+        );
+        assert_eq!(
+            resolve_overrides(EntryPoint::Module("toolshed"), None).interpreter,
+            Some(OneOrMany::One("out/env/bin/python".into()))
+        );
+    }
+
+    #[test]
+    fn should_apply_profile_after_overrides() {
+        assert_eq!(
+            resolve_overrides(EntryPoint::Module("tools"), Some("debug")).interpreter,
+            Some(OneOrMany::One("out/debug/bin/python".into()))
+        );
+    }
+
+    #[test]
+    fn should_require_something_to_match_in_overrides() {
+        let config = Config::parse(
+            r#"
+[tool.rpy]
+interpreter = 'python'
+
+[[tool.rpy.override]]
+interpreter = 'python3'
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .resolve(EntryPoint::Other, None)
+                .unwrap_err()
+                .to_string(),
+            "Each [[tool.rpy.override]] must have a 'script' or 'module' to match"
+        );
+    }
+
+    #[test]
+    fn should_merge_env_tables_when_layering() {
+        let config = Config::parse(
+            r#"
+[tool.rpy]
+interpreter = 'python'
+env_remove = ['PYTHONSTARTUP']
+
+[tool.rpy.env]
+OMP_NUM_THREADS = '4'
+MPLBACKEND = 'Agg'
+
+[tool.rpy.profiles.dev.env]
+PYTHONDEVMODE = '1'
+OMP_NUM_THREADS = '1'
+
+[tool.rpy.profiles.dev]
+env_remove = ['PYTHONWARNINGS']
+"#,
+        )
+        .unwrap();
+        let settings = config.resolve(EntryPoint::Other, Some("dev")).unwrap();
+        assert_eq!(
+            settings.env.unwrap().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("MPLBACKEND".to_string(), "Agg".to_string()),
+                ("OMP_NUM_THREADS".to_string(), "1".to_string()),
+                ("PYTHONDEVMODE".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(
+            settings.env_remove.unwrap(),
+            vec!["PYTHONSTARTUP".to_string(), "PYTHONWARNINGS".to_string()]
+        );
+    }
+
+    #[test]
+    fn should_parse_pre_run_steps() {
+        let config = Config::parse(
+            r#"
+[tool.rpy]
+interpreter = 'python'
+
+[[tool.rpy.pre_run]]
+name = 'deps'
+cmd = 'make --quiet deps'
+inputs = ['requirements.txt']
+
+[[tool.rpy.pre_run]]
+cmd = ['make', 'protos']
+cwd = 'gen'
+env = { PROTOC = 'protoc-3' }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.resolve(EntryPoint::Other, None).unwrap().pre_run,
+            Some(PreRun::Steps(vec![
+                PreRunStep {
+                    name: Some("deps".into()),
+                    cmd: CommandLine::Shell("make --quiet deps".into()),
+                    cwd: None,
+                    env: BTreeMap::new(),
+                    inputs: Some(vec!["requirements.txt".into()]),
+                },
+                PreRunStep {
+                    name: None,
+                    cmd: CommandLine::Argv(vec!["make".into(), "protos".into()]),
+                    cwd: Some("gen".into()),
+                    env: BTreeMap::from([("PROTOC".into(), "protoc-3".into())]),
+                    inputs: None,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn should_clear_pre_run_with_an_empty_array() {
+        let doc = r#"
+[tool.rpy]
+interpreter = 'python'
+pre_run = ['make', 'deps']
+
+[tool.rpy.profiles.quick]
+pre_run = []
+"#;
+        let config = Config::parse(doc).unwrap();
+        assert_eq!(
+            config.resolve(EntryPoint::Other, None).unwrap().pre_run,
+            Some(PreRun::Command(CommandLine::Argv(vec![
+                "make".into(),
+                "deps".into()
+            ])))
+        );
+        let config = Config::parse(doc).unwrap();
+        assert_eq!(
+            config
+                .resolve(EntryPoint::Other, Some("quick"))
+                .unwrap()
+                .pre_run,
+            Some(PreRun::Steps(vec![]))
+        );
+    }
+
+    #[test]
+    fn should_read_inline_script_metadata() {
+        let config = Config::from_script_metadata(
+            r#"
+requires-python = ">=3.11"
+dependencies = ["requests<3"]
+
+[tool.uv]
+exclude-newer = "2024-01-01T00:00:00Z"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.requires_python(), Some(">=3.11"));
+        assert_eq!(config.dependencies(), ["requests<3".to_string()]);
+        assert_eq!(
+            config.resolve(EntryPoint::Other, None).unwrap(),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn should_layer_config_files() {
+        let base = Config::parse(
+            r#"
+[tool.rpy]
+interpreter = 'python'
+env = { A = '1', B = '1' }
+
+[tool.rpy.profiles.dev]
+bin_path = 'bin'
+
+[[tool.rpy.override]]
+module = 'app'
+safe_path = false
+"#,
+        )
+        .unwrap();
+        let over: PyConfig = toml::from_str(
+            r#"
+env = { B = '2' }
+
+[profiles.dev]
+no_user_site = false
+
+[[override]]
+module = 'app'
+safe_path = true
+"#,
+        )
+        .unwrap();
+        let config = Config {
+            tool: Tool {
+                rpy: base.tool.rpy.layer(over),
+            },
+            project: None,
+            dependencies: vec![],
+        };
+        let settings = config
+            .resolve(EntryPoint::Module("app"), Some("dev"))
+            .unwrap();
+        assert_eq!(settings.interpreter, Some(OneOrMany::One("python".into())));
+        assert_eq!(
+            settings.env.unwrap(),
+            BTreeMap::from([("A".into(), "1".into()), ("B".into(), "2".into())])
+        );
+        assert_eq!(settings.bin_path.as_deref(), Some("bin"));
+        assert_eq!(settings.no_user_site, Some(false));
+        assert_eq!(settings.safe_path, Some(true));
+    }
+}
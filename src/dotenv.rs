@@ -0,0 +1,145 @@
+#![deny(warnings)]
+
+use std::fs;
+use std::path::Path;
+
+use eyre::{Result, WrapErr, eyre};
+
+/// Reads the `KEY=VALUE` pairs from a dotenv file, in the order they appear.
+pub fn load(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents =
+        fs::read_to_string(path).wrap_err(format!("Unable to read {}", path.display()))?;
+    parse(&contents).map_err(|(line, error)| eyre!("{}:{line}: {error}", path.display()))
+}
+
+/// Parses dotenv syntax: blank lines and `#` comments are ignored, a leading `export ` is
+/// allowed, single-quoted values are literal, double-quoted values understand `\n`, `\t`, `\"`,
+/// `\\` and `\$`, and unquoted values are trimmed and may end in a ` #` comment. No variable
+/// expansion is done.
+fn parse(contents: &str) -> std::result::Result<Vec<(String, String)>, (usize, String)> {
+    let mut result = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| (line_number, "expected KEY=VALUE".to_string()))?;
+        let key = key.trim_end();
+        if !is_valid_key(key) {
+            return Err((line_number, format!("invalid variable name '{key}'")));
+        }
+        let value = parse_value(value.trim_start()).map_err(|error| (line_number, error))?;
+        result.push((key.to_string(), value));
+    }
+    Ok(result)
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(value: &str) -> std::result::Result<String, String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let (inner, rest) = quoted
+            .split_once('\'')
+            .ok_or_else(|| "unterminated single-quoted value".to_string())?;
+        check_trailing(rest)?;
+        return Ok(inner.to_string());
+    }
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    check_trailing(chars.as_str())?;
+                    return Ok(result);
+                }
+                '\\' => match chars.next() {
+                    Some('n') => result.push('\n'),
+                    Some('t') => result.push('\t'),
+                    Some(c @ ('"' | '\\' | '$')) => result.push(c),
+                    Some(c) => return Err(format!("unknown escape '\\{c}'")),
+                    None => break,
+                },
+                c => result.push(c),
+            }
+        }
+        return Err("unterminated double-quoted value".to_string());
+    }
+    let value = match value.find(" #") {
+        Some(index) => &value[..index],
+        None => value,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+fn check_trailing(rest: &str) -> std::result::Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{rest}' after quoted value"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_parse_simple_assignments() {
+        assert_eq!(
+            parse("# settings\n\nA=1\nexport B = two words # comment\nC=\n").unwrap(),
+            pairs(&[("A", "1"), ("B", "two words"), ("C", "")])
+        );
+    }
+
+    #[test]
+    fn should_parse_quoted_values() {
+        assert_eq!(
+            parse("A='lit $x \\n'\nB=\"tab\\there \\\"q\\\" \\$x\" # done\nC=\"#not comment\"")
+                .unwrap(),
+            pairs(&[
+                ("A", "lit $x \\n"),
+                ("B", "tab\there \"q\" $x"),
+                ("C", "#not comment"),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_report_malformed_lines() {
+        assert_eq!(
+            parse("A=1\njust words\n").unwrap_err(),
+            (2, "expected KEY=VALUE".to_string())
+        );
+        assert_eq!(
+            parse("1A=1").unwrap_err(),
+            (1, "invalid variable name '1A'".to_string())
+        );
+        assert_eq!(
+            parse("\n\nA='open").unwrap_err(),
+            (3, "unterminated single-quoted value".to_string())
+        );
+        assert_eq!(
+            parse("A=\"x\" y").unwrap_err(),
+            (1, "unexpected 'y' after quoted value".to_string())
+        );
+    }
+}
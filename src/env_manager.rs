@@ -0,0 +1,90 @@
+#![deny(warnings)]
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, eyre};
+
+use crate::config::{EnvManager, EnvTool};
+use crate::interpreter::find_on_path;
+use crate::pre_run::{Hook, Step};
+
+/// The steps that create and sync a `[tool.rpy.env_manager]` environment, and the interpreter in
+/// it. They run with the pre_run steps (first), so share their lock and are skipped the same way:
+/// creating the environment only when it is missing, and syncing it only when the lockfile has
+/// changed or the environment has been recreated. With `requirements`, the sync step writes them
+/// to the lockfile first. Syncing with pip only installs: unlike uv, it leaves packages that are
+/// no longer in the lockfile.
+pub fn steps(
+    project_root: &Path,
+    manager: &EnvManager,
+    requirements: Option<String>,
+) -> Result<(Vec<Step>, PathBuf)> {
+    if manager.lockfile.is_empty() {
+        return Err(eyre!("[tool.rpy.env_manager] needs a lockfile"));
+    }
+    let env_dir = manager.env_dir.as_deref().unwrap_or(".venv");
+    let env_path = project_root.join(env_dir);
+    let python = env_path.join("bin/python");
+    let tool = match manager.tool {
+        Some(tool) => tool,
+        None if find_on_path("uv").is_some() => EnvTool::Uv,
+        None => EnvTool::Pip,
+    };
+    let to_string = |path: &Path| path.to_string_lossy().to_string();
+    let mut from_wheelhouse = vec![];
+    if let Some(wheelhouse) = &manager.wheelhouse {
+        from_wheelhouse.extend([
+            "--no-index".to_string(),
+            "--find-links".to_string(),
+            to_string(&project_root.join(wheelhouse)),
+        ]);
+    }
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let (create, sync) = match tool {
+        EnvTool::Uv => {
+            let mut create = args(&["uv", "venv", "--allow-existing", "--quiet"]);
+            if let Some(base_python) = &manager.python {
+                create.extend(args(&["--python", base_python]));
+            }
+            create.push(to_string(&env_path));
+            let mut sync = args(&["uv", "pip", "sync", "--quiet", "--python"]);
+            sync.push(to_string(&python));
+            if !from_wheelhouse.is_empty() {
+                sync.push("--offline".to_string());
+            }
+            sync.extend(from_wheelhouse);
+            sync.push(to_string(&project_root.join(&manager.lockfile)));
+            (create, sync)
+        }
+        EnvTool::Pip => {
+            let mut create = args(&[manager.python.as_deref().unwrap_or("python3"), "-m", "venv"]);
+            create.push(to_string(&env_path));
+            let mut sync = vec![to_string(&python)];
+            sync.extend(args(&["-m", "pip", "install", "--quiet", "-r"]));
+            sync.push(to_string(&project_root.join(&manager.lockfile)));
+            sync.extend(from_wheelhouse);
+            (create, sync)
+        }
+    };
+    let marker = format!("{env_dir}/pyvenv.cfg");
+    let lockfile = project_root.join(&manager.lockfile);
+    let step = |name: &str, argv: Vec<String>, inputs: Vec<String>| Step {
+        hook: Hook::PreRun,
+        name: format!("env_manager {name}"),
+        cmd: argv.join(" "),
+        argv,
+        via_shell: false,
+        cwd: project_root.to_path_buf(),
+        env: vec![],
+        inputs: Some(inputs),
+        writes: None,
+    };
+    let steps = vec![
+        step("create", create, vec![marker.clone()]),
+        Step {
+            writes: requirements.map(|requirements| (lockfile, requirements)),
+            ..step("sync", sync, vec![manager.lockfile.clone(), marker])
+        },
+    ];
+    Ok((steps, python))
+}
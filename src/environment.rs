@@ -0,0 +1,168 @@
+#![deny(warnings)]
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use eyre::{Result, WrapErr, eyre};
+
+/// The changes rpy makes to the environment it was itself run with.
+#[derive(Debug, Default)]
+pub struct Environment {
+    changes: BTreeMap<String, Option<OsString>>,
+}
+
+impl Environment {
+    pub fn set(&mut self, name: &str, value: impl Into<OsString>) {
+        self.changes.insert(name.to_string(), Some(value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.changes.insert(name.to_string(), None);
+    }
+
+    /// The value `name` will have in the environment python is run with.
+    pub fn get(&self, name: &str) -> Option<OsString> {
+        match self.changes.get(name) {
+            Some(value) => value.clone(),
+            None => env::var_os(name),
+        }
+    }
+
+    pub fn prepend_paths(&mut self, name: &str, paths: Vec<PathBuf>) -> Result<()> {
+        let mut all = paths;
+        if let Some(current) = self.get(name) {
+            all.extend(env::split_paths(&current));
+        }
+        self.set_paths(name, all)
+    }
+
+    pub fn append_paths(&mut self, name: &str, paths: Vec<PathBuf>) -> Result<()> {
+        let mut all = match self.get(name) {
+            Some(current) => env::split_paths(&current).collect(),
+            None => vec![],
+        };
+        all.extend(paths);
+        self.set_paths(name, all)
+    }
+
+    fn set_paths(&mut self, name: &str, paths: Vec<PathBuf>) -> Result<()> {
+        let joined = env::join_paths(paths).wrap_err(format!("Unable to build {name}"))?;
+        self.set(name, joined);
+        Ok(())
+    }
+
+    /// Each variable rpy changes, with its new value or `None` if it is removed.
+    pub fn changes(&self) -> impl Iterator<Item = (&str, Option<&OsStr>)> {
+        self.changes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    pub fn apply(&self, cmd: &mut Command) {
+        for (name, value) in &self.changes {
+            match value {
+                Some(value) => cmd.env(name, value),
+                None => cmd.env_remove(name),
+            };
+        }
+    }
+}
+
+/// The values available to `${...}` references in `[tool.rpy.env]` and friends.
+pub struct Interpolation<'a> {
+    pub project_root: &'a Path,
+    pub source_root: &'a Path,
+}
+
+impl Interpolation<'_> {
+    /// Expands `${PROJECT_ROOT}`, `${SOURCE_ROOT}` and `${env:VAR}` in `value`. `$$` is a literal
+    /// `$`, and an unset `${env:VAR}` expands to nothing.
+    pub fn expand(&self, value: &str) -> Result<String> {
+        let mut result = String::new();
+        let mut rest = value;
+        while let Some(index) = rest.find('$') {
+            result.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                result.push('$');
+                rest = after;
+                continue;
+            }
+            let Some(reference) = rest.strip_prefix('{') else {
+                result.push('$');
+                continue;
+            };
+            let end = reference
+                .find('}')
+                .ok_or_else(|| eyre!("Unterminated '${{' in '{value}'"))?;
+            let name = &reference[..end];
+            rest = &reference[end + 1..];
+            match name {
+                "PROJECT_ROOT" => result.push_str(&self.project_root.to_string_lossy()),
+                "SOURCE_ROOT" => result.push_str(&self.source_root.to_string_lossy()),
+                _ => match name.strip_prefix("env:") {
+                    Some(var) => result.push_str(&env::var(var).unwrap_or_default()),
+                    None => return Err(eyre!("Unknown variable '${{{name}}}' in '{value}'")),
+                },
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Interpolation;
+
+    fn expand(value: &str) -> eyre::Result<String> {
+        Interpolation {
+            project_root: Path::new("/prj"),
+            source_root: Path::new("/prj/src/py"),
+        }
+        .expand(value)
+    }
+
+    #[test]
+    fn should_leave_plain_values_alone() {
+        assert_eq!(expand("agg").unwrap(), "agg");
+        assert_eq!(expand("").unwrap(), "");
+        assert_eq!(expand("cost: $5").unwrap(), "cost: $5");
+    }
+
+    #[test]
+    fn should_expand_roots() {
+        assert_eq!(expand("${PROJECT_ROOT}/data").unwrap(), "/prj/data");
+        assert_eq!(
+            expand("${SOURCE_ROOT}:${PROJECT_ROOT}").unwrap(),
+            "/prj/src/py:/prj"
+        );
+        assert_eq!(expand("$${PROJECT_ROOT}").unwrap(), "${PROJECT_ROOT}");
+    }
+
+    #[test]
+    fn should_expand_environment_variables() {
+        assert_eq!(expand("${env:RPY_SURELY_NOT_SET}").unwrap(), "");
+        assert_eq!(
+            expand("${env:PATH}").unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_bad_references() {
+        assert_eq!(
+            expand("${HOME}").unwrap_err().to_string(),
+            "Unknown variable '${HOME}' in '${HOME}'"
+        );
+        assert_eq!(
+            expand("a${PROJECT_ROOT").unwrap_err().to_string(),
+            "Unterminated '${' in 'a${PROJECT_ROOT'"
+        );
+    }
+}
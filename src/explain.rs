@@ -0,0 +1,193 @@
+#![deny(warnings)]
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use serde::Serialize;
+
+use crate::options::Format;
+use crate::plan::Project;
+use crate::pre_run::{self, Step};
+use crate::rpy::Rpy;
+
+/// Everything rpy has decided about how to run python, for `--rpy-explain`.
+#[derive(Serialize, Debug)]
+struct Explanation<'a> {
+    config_files: &'a [PathBuf],
+    inline_metadata: bool,
+    project_root: &'a Path,
+    profile: Option<&'a str>,
+    /// `null` when the interpreter can't be used yet, because pre_run has still to create it.
+    interpreter: Option<&'a Path>,
+    interpreter_source: Option<&'a str>,
+    interpreter_candidates: Vec<String>,
+    /// Why the interpreter can't be used yet.
+    interpreter_error: Option<String>,
+    python_version: Option<&'a str>,
+    /// `null` without an interpreter.
+    argv: Option<Vec<String>>,
+    args: &'a [String],
+    /// As configured if it doesn't exist (yet), when `bin_path_error` says why.
+    bin_path: Option<PathBuf>,
+    bin_path_error: Option<String>,
+    /// The variables rpy changes, with `null` for those it removes.
+    environment: BTreeMap<&'a str, Option<String>>,
+    pre_run: Vec<ExplainedStep<'a>>,
+    post_run: Vec<ExplainedStep<'a>>,
+    supervise: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ExplainedStep<'a> {
+    name: &'a str,
+    cmd: &'a str,
+    cwd: &'a Path,
+    will_run: bool,
+}
+
+fn explain_steps<'a>(steps: &'a [Step], will_run: &[bool]) -> Vec<ExplainedStep<'a>> {
+    steps
+        .iter()
+        .zip(will_run)
+        .map(|(step, will_run)| ExplainedStep {
+            name: &step.name,
+            cmd: &step.cmd,
+            cwd: &step.cwd,
+            will_run: *will_run,
+        })
+        .collect()
+}
+
+/// Prints what running `cmdline_args` in `project` would involve, without running anything.
+/// An interpreter that can't be used is reported rather than an error if pre_run (which may
+/// create it) would run first, as is a missing `bin_path`.
+pub fn print(project: &Project, cmdline_args: &Rpy, format: Format, verbose: bool) -> Result<()> {
+    let pre_run_pending = pre_run::pending(
+        &project.root,
+        project.pre_run_dir.as_deref(),
+        &project.pre_run,
+    )?;
+    let (interpreter, info, environment, interpreter_error) =
+        match project.launch(cmdline_args, verbose) {
+            Ok(launch) => (
+                Some(launch.interpreter),
+                launch.info,
+                launch.environment,
+                None,
+            ),
+            Err(e) if pre_run_pending.contains(&true) => (
+                None,
+                None,
+                project.environment(verbose)?,
+                Some(format!("{e:#}")),
+            ),
+            Err(e) => return Err(e),
+        };
+    let bin_path = project.bin_path();
+    let args = cmdline_args
+        .make_args()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    let explanation = Explanation {
+        config_files: &project.config_files,
+        inline_metadata: project.inline_metadata,
+        project_root: &project.root,
+        profile: project.profile.as_deref(),
+        interpreter: interpreter
+            .as_ref()
+            .map(|interpreter| interpreter.path.as_path()),
+        interpreter_source: interpreter
+            .as_ref()
+            .map(|interpreter| interpreter.source.as_str()),
+        interpreter_candidates: project.interpreter_candidates(),
+        interpreter_error,
+        python_version: info.as_ref().map(|info| info.version.as_str()),
+        argv: interpreter.as_ref().map(|interpreter| {
+            let mut argv = vec![interpreter.path.to_string_lossy().to_string()];
+            argv.extend(args.iter().cloned());
+            argv
+        }),
+        args: &args,
+        bin_path: bin_path.clone().map(|(bin_path, _)| bin_path),
+        bin_path_error: bin_path.and_then(|(_, error)| error),
+        environment: environment
+            .changes()
+            .map(|(name, value)| (name, value.map(|v| v.to_string_lossy().to_string())))
+            .collect(),
+        pre_run: explain_steps(&project.pre_run, &pre_run_pending),
+        post_run: explain_steps(&project.post_run, &vec![true; project.post_run.len()]),
+        supervise: project.supervise,
+    };
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&explanation)?),
+        Format::Text => print_text(&explanation),
+    }
+    Ok(())
+}
+
+fn print_text(explanation: &Explanation) {
+    for file in explanation.config_files {
+        if explanation.inline_metadata {
+            println!("script metadata: {}", file.display());
+        } else {
+            println!("config: {}", file.display());
+        }
+    }
+    println!("project root: {}", explanation.project_root.display());
+    println!("profile: {}", explanation.profile.unwrap_or("(none)"));
+    match (explanation.interpreter, explanation.interpreter_source) {
+        (Some(interpreter), Some(source)) => {
+            println!("python: {} (from {source})", interpreter.display())
+        }
+        _ => println!(
+            "python: not usable until pre_run has run ({})",
+            explanation.interpreter_error.as_deref().unwrap_or_default()
+        ),
+    }
+    if explanation.interpreter.is_none() && !explanation.interpreter_candidates.is_empty() {
+        println!(
+            "python candidates: {}",
+            explanation.interpreter_candidates.join(", ")
+        );
+    }
+    if let Some(version) = explanation.python_version {
+        println!("python version: {version}");
+    }
+    if let Some(bin_path) = &explanation.bin_path {
+        match &explanation.bin_path_error {
+            Some(error) if explanation.pre_run.iter().any(|step| step.will_run) => println!(
+                "bin_path: {} (not usable until pre_run has run: {error})",
+                bin_path.display()
+            ),
+            Some(error) => println!("bin_path: {} ({error})", bin_path.display()),
+            None => println!("bin_path: {}", bin_path.display()),
+        }
+    }
+    match &explanation.argv {
+        Some(argv) => println!("argv: {}", argv.join(" ")),
+        None => println!("argv: <python> {}", explanation.args.join(" ")),
+    }
+    for (name, value) in &explanation.environment {
+        match value {
+            Some(value) => println!("env: {name}={value}"),
+            None => println!("env: unset {name}"),
+        }
+    }
+    for step in &explanation.pre_run {
+        let status = if step.will_run {
+            "would run"
+        } else {
+            "skipped, inputs unchanged"
+        };
+        println!("pre_run: {} ({status}): {}", step.name, step.cmd);
+    }
+    for step in &explanation.post_run {
+        println!("post_run: {}: {}", step.name, step.cmd);
+    }
+    println!(
+        "supervised: {}",
+        if explanation.supervise { "yes" } else { "no" }
+    );
+}
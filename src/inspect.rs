@@ -0,0 +1,142 @@
+#![deny(warnings)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+
+use crate::interpreter;
+use crate::options::Options;
+use crate::plan::Project;
+use crate::pre_run;
+use crate::rpy::Rpy;
+
+/// The version of the `--rpy-inspect` output. Fields may be added without changing it; it
+/// changes when any are removed or change meaning.
+const SCHEMA_VERSION: u32 = 1;
+
+/// How rpy would run a file, or anything in a directory, for editors and other tools.
+#[derive(Serialize, Debug)]
+struct Inspection<'a> {
+    schema_version: u32,
+    path: &'a Path,
+    project_root: &'a Path,
+    config_files: &'a [PathBuf],
+    inline_metadata: bool,
+    profile: Option<&'a str>,
+    interpreter: InspectedInterpreter<'a>,
+    source_roots: &'a [PathBuf],
+    /// Canonical if it exists, otherwise as configured, when `bin_path_error` says why.
+    bin_path: Option<PathBuf>,
+    bin_path_error: Option<String>,
+    /// The variables rpy changes, with `null` for those it removes.
+    environment: BTreeMap<&'a str, Option<String>>,
+    pre_run: Vec<InspectedStep<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct InspectedInterpreter<'a> {
+    /// As rpy runs it: a path, or a name looked up on the `PATH`. `null` if none can be used
+    /// (perhaps until pre_run creates it), when `error` says why.
+    raw: Option<&'a Path>,
+    /// The file that is, with symlinks resolved, if it exists.
+    canonical: Option<PathBuf>,
+    source: Option<&'a str>,
+    /// The configured interpreters, or the environment manager's. Empty when discovered.
+    candidates: Vec<String>,
+    version: Option<&'a str>,
+    requires_python: Option<&'a str>,
+    /// Whether `version` satisfies `requires_python`, when both are known.
+    satisfies_requires_python: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct InspectedStep<'a> {
+    name: &'a str,
+    cmd: &'a str,
+    will_run: bool,
+}
+
+/// Prints, as JSON, how rpy would run the file `path` or, if it's a directory, anything in it.
+pub fn print(path: &Path, options: &Options) -> Result<()> {
+    let path = fs::canonicalize(path).wrap_err(format!("Unable to inspect {}", path.display()))?;
+    let mut options = options.clone();
+    let cmdline_args = if path.is_dir() {
+        options.project = Some(path.clone());
+        Rpy::parse(vec![])
+    } else {
+        Rpy::parse(vec![path.to_string_lossy().to_string()])
+    };
+    let project = Project::load(&cmdline_args, &options, false)?;
+    // Unlike a run, not being able to use the interpreter or bin_path is reported rather than
+    // an error, so that editors still get everything else.
+    let (interpreter, check, error) = match project.interpreter() {
+        Ok(interpreter) => match project.check_requires_python(&interpreter, false) {
+            Ok(check) => (Some(interpreter), check, None),
+            Err(e) => (Some(interpreter), None, Some(format!("{e:#}"))),
+        },
+        Err(e) => (None, None, Some(format!("{e:#}"))),
+    };
+    let search_path = project.search_path()?;
+    let raw = interpreter
+        .as_ref()
+        .map(|interpreter| interpreter.path.as_path());
+    let canonical = raw
+        .and_then(|raw| {
+            if raw.is_absolute() {
+                Some(raw.to_path_buf())
+            } else {
+                interpreter::find_on(&raw.to_string_lossy(), search_path.as_deref())
+            }
+        })
+        .and_then(|found| fs::canonicalize(found).ok());
+    let environment = project.environment(false)?;
+    let (bin_path, bin_path_error) = project.bin_path().unzip();
+    let pending = pre_run::pending(
+        &project.root,
+        project.pre_run_dir.as_deref(),
+        &project.pre_run,
+    )?;
+    let inspection = Inspection {
+        schema_version: SCHEMA_VERSION,
+        path: &path,
+        project_root: &project.root,
+        config_files: &project.config_files,
+        inline_metadata: project.inline_metadata,
+        profile: project.profile.as_deref(),
+        interpreter: InspectedInterpreter {
+            raw,
+            canonical,
+            source: interpreter
+                .as_ref()
+                .map(|interpreter| interpreter.source.as_str()),
+            candidates: project.interpreter_candidates(),
+            version: check.as_ref().map(|check| check.info.version.as_str()),
+            requires_python: check.as_ref().map(|check| check.requires_python.as_str()),
+            satisfies_requires_python: check.as_ref().map(|check| check.satisfied),
+            error,
+        },
+        source_roots: project.source_roots(),
+        bin_path,
+        bin_path_error: bin_path_error.flatten(),
+        environment: environment
+            .changes()
+            .map(|(name, value)| (name, value.map(|v| v.to_string_lossy().to_string())))
+            .collect(),
+        pre_run: project
+            .pre_run
+            .iter()
+            .zip(pending)
+            .map(|(step, will_run)| InspectedStep {
+                name: &step.name,
+                cmd: &step.cmd,
+                will_run,
+            })
+            .collect(),
+    };
+    println!("{}", serde_json::to_string_pretty(&inspection)?);
+    Ok(())
+}
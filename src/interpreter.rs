@@ -0,0 +1,238 @@
+#![deny(warnings)]
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use eyre::{Result, WrapErr, eyre};
+
+use crate::cache::{self, InterpreterInfo, QueryResult};
+use crate::version::{Specifiers, Version};
+
+/// The interpreter rpy will run, and the rule that chose it.
+#[derive(Debug)]
+pub struct Interpreter {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+/// Resolves the interpreters given in configuration (or `RPY_INTERPRETER`), returning the first
+/// that exists and is executable along with its index in `candidates`. Anything containing a `/`
+/// is relative to the project root and canonicalized, otherwise it's looked up on `search_path`:
+/// the `PATH` python will be run with, which has `bin_path` first.
+pub fn first_usable(
+    project_root: &Path,
+    candidates: &[String],
+    search_path: Option<&OsStr>,
+) -> Result<(PathBuf, usize)> {
+    let mut rejections = vec![];
+    for (index, raw_interpreter) in candidates.iter().enumerate() {
+        match check_candidate(project_root, raw_interpreter, search_path) {
+            Ok(interpreter) => return Ok((interpreter, index)),
+            Err(reason) => rejections.push(format!("  {raw_interpreter}: {reason}")),
+        }
+    }
+    if candidates.is_empty() {
+        return Err(eyre!("The list of interpreters in [tool.rpy] is empty"));
+    }
+    Err(eyre!(
+        "None of the configured interpreters could be used:\n{}",
+        rejections.join("\n")
+    ))
+}
+
+fn check_candidate(
+    project_root: &Path,
+    raw_interpreter: &str,
+    search_path: Option<&OsStr>,
+) -> std::result::Result<PathBuf, String> {
+    if raw_interpreter.contains('/') {
+        let interpreter = project_root.join(Path::new(raw_interpreter));
+        check_executable(&interpreter)?;
+        interpreter
+            .canonicalize()
+            .map_err(|err| format!("unable to canonicalize: {err}"))
+    } else {
+        let found = find_on(raw_interpreter, search_path).ok_or("not found on the PATH")?;
+        check_executable(&found)?;
+        Ok(Path::new(raw_interpreter).to_path_buf())
+    }
+}
+
+fn check_executable(path: &Path) -> std::result::Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|err| match err.kind() {
+        ErrorKind::NotFound => format!("{} does not exist", path.display()),
+        _ => format!("unable to read {}: {err}", path.display()),
+    })?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    if metadata.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{} is not executable", path.display()));
+    }
+    Ok(())
+}
+
+/// Finds an interpreter when none is configured, trying in order:
+/// `.venv/bin/python` and `venv/bin/python` in the project root, `$VIRTUAL_ENV/bin/python`, the
+/// version named in the project's `.python-version` file, and finally the newest `python3.N` on the
+/// `PATH` that satisfies `requires-python`.
+///
+/// Virtual environment interpreters are deliberately not canonicalized: they are usually symlinks
+/// to the base interpreter, and python only finds the environment if run via the link.
+pub fn discover(project_root: &Path, requires_python: Option<&Specifiers>) -> Result<Interpreter> {
+    for venv in [".venv", "venv"] {
+        let python = project_root.join(venv).join("bin/python");
+        if python.is_file() {
+            return Ok(Interpreter {
+                path: python,
+                source: format!("{venv}/bin/python"),
+            });
+        }
+    }
+    if let Some(virtual_env) = env::var_os("VIRTUAL_ENV").filter(|v| !v.is_empty()) {
+        let python = Path::new(&virtual_env).join("bin/python");
+        if python.is_file() {
+            return Ok(Interpreter {
+                path: python,
+                source: "$VIRTUAL_ENV".to_string(),
+            });
+        }
+    }
+    let python_version_file = project_root.join(".python-version");
+    if python_version_file.is_file()
+        && let Some(python) = from_python_version_file(&python_version_file)?
+    {
+        return Ok(Interpreter {
+            path: python,
+            source: ".python-version".to_string(),
+        });
+    }
+    if let Some(requires_python) = requires_python
+        && let Some(python) = newest_on_path(requires_python)
+    {
+        return Ok(Interpreter {
+            path: python,
+            source: format!("requires-python {requires_python}"),
+        });
+    }
+    Err(eyre!(
+        "No interpreter configured in [tool.rpy] and none could be discovered (tried .venv/bin/python, \
+         venv/bin/python, $VIRTUAL_ENV, .python-version and requires-python)"
+    ))
+}
+
+fn from_python_version_file(path: &Path) -> Result<Option<PathBuf>> {
+    let contents =
+        fs::read_to_string(path).wrap_err(format!("Unable to read {}", path.display()))?;
+    let Some(version) = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+    else {
+        return Ok(None);
+    };
+    if let Some(python) = from_pyenv(version) {
+        return Ok(Some(python));
+    }
+    Ok(Version::parse(version).ok().and_then(|version| {
+        let (major, minor) = version.major_minor();
+        find_on_path(&format!("python{major}.{minor}"))
+    }))
+}
+
+/// Finds `version` among pyenv's installed versions, allowing a prefix like `3.11` to match the
+/// newest `3.11.x`.
+fn from_pyenv(version: &str) -> Option<PathBuf> {
+    let pyenv_root = env::var_os("PYENV_ROOT")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".pyenv")))?;
+    let versions = pyenv_root.join("versions");
+    let exact = versions.join(version).join("bin/python");
+    if exact.is_file() {
+        return Some(exact);
+    }
+    let prefix = format!("{version}.");
+    fs::read_dir(&versions)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with(&prefix))
+        .filter_map(|name| Some((Version::parse(&name).ok()?, name)))
+        .max()
+        .map(|(_, name)| versions.join(name).join("bin/python"))
+        .filter(|python| python.is_file())
+}
+
+fn newest_on_path(requires_python: &Specifiers) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let (major, minor) = name.strip_prefix("python")?.split_once('.')?;
+            let series = (major.parse::<u64>().ok()?, minor.parse::<u64>().ok()?);
+            requires_python
+                .allows_series(series.0, series.1)
+                .then(|| (series, entry.path()))
+        })
+        .filter(|(_, path)| path.is_file())
+        .max_by_key(|(series, _)| *series)
+        .and_then(|((major, minor), _)| find_on_path(&format!("python{major}.{minor}")))
+}
+
+/// Finds the first file called `name` on rpy's own `PATH`.
+pub fn find_on_path(name: &str) -> Option<PathBuf> {
+    find_on(name, env::var_os("PATH").as_deref())
+}
+
+/// Finds the first file called `name` in the directories of `search_path`, a `PATH`-like list.
+pub fn find_on(name: &str, search_path: Option<&OsStr>) -> Option<PathBuf> {
+    env::split_paths(search_path?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+const QUERY: &str = "import sys, sysconfig
+print(*sys.version_info[:3], sep='.')
+print(sys.prefix)
+print(sysconfig.get_platform())";
+
+/// Finds out about `interpreter` by running it, or from the cache if it hasn't changed since. A
+/// bare name is looked up on `search_path`, as for `first_usable`.
+pub fn info(interpreter: &Path, search_path: Option<&OsStr>) -> Result<InterpreterInfo> {
+    let resolved = if interpreter.is_absolute() {
+        interpreter.to_path_buf()
+    } else {
+        let found = find_on(&interpreter.to_string_lossy(), search_path)
+            .ok_or_else(|| eyre!("Unable to find {} on the PATH", interpreter.display()))?;
+        std::path::absolute(found)?
+    };
+    let failed = || format!("Unable to query python interpreter {}", resolved.display());
+    cache::interpreter_info(&resolved, || {
+        let output = Command::new(&resolved)
+            .args(["-c", QUERY])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .wrap_err_with(failed)?;
+        if !output.status.success() {
+            return Err(eyre!("{}: {}", failed(), output.status));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut lines = stdout.lines().map(str::trim);
+        match (lines.next(), lines.next(), lines.next()) {
+            (Some(version), Some(prefix), Some(platform)) => Ok(QueryResult {
+                version: version.to_string(),
+                prefix: prefix.to_string(),
+                platform: platform.to_string(),
+            }),
+            _ => Err(eyre!("{}: unexpected output '{}'", failed(), stdout.trim())),
+        }
+    })
+}
@@ -1,121 +1,182 @@
 #![deny(warnings)]
 
-use std::os::unix::process::CommandExt;
-use std::path::Path;
-use std::process::Command;
-use std::process::Stdio;
+use std::env;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::exit;
-use std::{env, fs};
+use std::time::Instant;
 
-use eyre::{ContextCompat, Report, Result, WrapErr, eyre};
-use serde::Deserialize;
+use eyre::{Report, Result, WrapErr, eyre};
 
-use crate::rpy::Rpy;
+use crate::options::{CacheCommand, Format, Options};
+use crate::plan::Project;
+use crate::rpy::{InvocationType, Rpy};
 
+mod activate;
+mod cache;
+mod config;
+mod dotenv;
+mod env_manager;
+mod environment;
+mod explain;
+mod inspect;
+mod interpreter;
+mod options;
+mod plan;
+mod pre_run;
 mod rpy;
+mod script_metadata;
+mod shell;
+mod supervise;
+mod telemetry;
+mod version;
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    tool: Tool,
-}
-
-#[derive(Deserialize, Debug)]
-struct Tool {
-    rpy: PyConfig,
-}
-
-#[derive(Deserialize, Debug)]
-struct PyConfig {
-    interpreter: String,
-    bin_path: Option<String>,
-    source_root: Option<String>,
-    pre_run: Option<String>,
-}
-
-fn pre_run(run_dir: &Path, pre_run_cmd: &str, verbose: bool) -> Result<()> {
-    if verbose {
-        println!("running pre_run: {pre_run_cmd}");
-    }
-    let args = ["-eu", "-o", "pipefail", "-c", pre_run_cmd];
-    let res = Command::new("bash")
-        .stderr(Stdio::inherit())
-        .stdout(os_pipe::dup_stderr()?)
-        .args(args)
-        .current_dir(run_dir)
-        .status()?;
-    if !res.success() {
-        return Err(eyre!(
-            "Pre-run step '{}' failed with exit code {}",
-            pre_run_cmd,
-            res.code().unwrap()
-        ));
+fn cache_command(command: CacheCommand) -> Result<()> {
+    match command {
+        CacheCommand::List => {
+            if let Some(dir) = cache::dir() {
+                println!("cache: {}", dir.display());
+            }
+            for info in cache::interpreter_infos()? {
+                println!("{}", info.path);
+                println!("  version: {}", info.version);
+                println!("  prefix: {}", info.prefix);
+                println!("  platform: {}", info.platform);
+            }
+        }
+        CacheCommand::Clear => {
+            if let Some(dir) = cache::clear()? {
+                println!("cleared: {}", dir.display());
+            }
+        }
     }
     Ok(())
 }
 
 fn run() -> Result<()> {
-    let cmdline_args = Rpy::parse(env::args().skip(1).collect());
-    if cmdline_args.print_banner {
+    let mut args = env::args().skip(1).collect();
+    let options = Options::parse(&mut args)?;
+    if let Some(command) = options.cache {
+        return cache_command(command);
+    }
+    if options.help {
+        print!("{}", options::HELP);
+        return Ok(());
+    }
+    if options.shell && !args.is_empty() {
+        return Err(eyre!(
+            "--rpy-shell starts a shell rather than python, so takes no python arguments (got '{}')",
+            args.join(" ")
+        ));
+    }
+    if let Some(path) = &options.inspect {
+        if !args.is_empty() {
+            return Err(eyre!(
+                "--rpy-inspect describes a path, so takes no python arguments (got '{}')",
+                args.join(" ")
+            ));
+        }
+        return inspect::print(path, &options);
+    }
+    let cmdline_args = Rpy::parse(args);
+    // Output meant for eval or for parsing must be all that reaches stdout.
+    let machine_readable = options.env.is_some() || options.explain == Some(Format::Json);
+    if cmdline_args.print_banner && !machine_readable {
         println!("Running under rpy version {}", env!("CARGO_PKG_VERSION"));
     }
 
-    let verbose = env::var("RPY_VERBOSE").is_ok_and(|x| x != "0");
-    let toml = cmdline_args.find_toml()?;
-    let project_root = toml.parent().wrap_err("Unable to get project root")?;
-    if verbose {
-        println!("project root: {}", project_root.display());
-        println!("toml: {}", toml.display());
+    let verbose =
+        !machine_readable && (options.verbose || env::var("RPY_VERBOSE").is_ok_and(|x| x != "0"));
+    let project = Project::load(&cmdline_args, &options, verbose)?;
+    if let Some(format) = options.explain {
+        return explain::print(&project, &cmdline_args, format, verbose);
     }
-    let toml_doc = fs::read_to_string(toml.as_path()).wrap_err("Unable to read pyproject.toml")?;
-    let config: Config = toml::from_str(&toml_doc)
-        .wrap_err("Unable to read toml document or find the rpy.tool configuration in it")?;
-    let py_config = config.tool.rpy;
-    let raw_interpreter = env::var("RPY_INTERPRETER").unwrap_or(py_config.interpreter);
-    if let Some(str) = py_config.pre_run {
-        pre_run(project_root, &str, verbose).wrap_err("Unable to run pre_run step")?;
+    if let Some(shell) = options.env {
+        activate::print(&project.environment(verbose)?, shell);
+        return Ok(());
     }
+    let mut pre_run_ms = None;
+    if !project.pre_run.is_empty() {
+        let start = Instant::now();
+        pre_run::run(
+            &project.root,
+            project.pre_run_dir.as_deref(),
+            &project.pre_run,
+            project.lock_timeout,
+            project.capture_lines,
+            verbose,
+        )
+        .wrap_err("Unable to run pre_run step")?;
+        pre_run_ms = Some(start.elapsed().as_millis() as u64);
+    }
+    let mut launch = project.launch(&cmdline_args, verbose)?;
+    if options.shell {
+        let mut cmd = shell::command(&project.root, &mut launch, verbose)?;
+        return Err(Report::new(cmd.exec()));
+    }
+    let mut cmd = launch.command();
 
-    let interpreter = if raw_interpreter.contains('/') {
-        project_root
-            .join(Path::new(&raw_interpreter))
-            .canonicalize()
-            .wrap_err("Unable to canonicalize interpreter path")?
-    } else {
-        Path::new(&raw_interpreter).to_path_buf()
-    };
-    let src_root = project_root.join(Path::new(&py_config.source_root.unwrap_or_default()));
+    if !project.supervise {
+        return Err(Report::new(cmd.exec()));
+    }
     if verbose {
-        println!("python: {}", interpreter.display());
-        println!("src_root: {}", src_root.display());
+        println!("supervising: {} post_run step(s)", project.post_run.len());
     }
-
-    let mut cmd = Command::new(interpreter);
-    cmd.args(cmdline_args.make_args());
-    cmd.env("PYTHONPATH", &src_root);
-    cmd.env("PYTHONNOUSERSITE", "1");
-    cmd.env("PYTHONSAFEPATH", "1");
-    cmd.env_remove("RPY_INTERPRETER");
-
-    if let Some(bin_path_str) = py_config.bin_path {
-        let cur_path = env::var("PATH").unwrap_or("".to_string());
-        let mut paths = env::split_paths(&cur_path).collect::<Vec<_>>();
-        let bin_path = project_root
-            .join(bin_path_str)
-            .canonicalize()
-            .wrap_err("Unable to canonicalize bin path")?;
-        if verbose {
-            println!("bin_path: {}", bin_path.display());
+    let start = Instant::now();
+    let status = supervise::spawn_and_wait(&mut cmd)?;
+    let script_ms = start.elapsed().as_millis() as u64;
+    let mut exit_env = vec![(
+        "RPY_EXIT_CODE".to_string(),
+        status
+            .code()
+            .or(status.signal().map(|signal| 128 + signal))
+            .unwrap_or(1)
+            .to_string(),
+    )];
+    if let Some(signal) = status.signal() {
+        exit_env.push((
+            "RPY_EXIT_SIGNAL".to_string(),
+            supervise::signal_name(signal).to_string(),
+        ));
+    }
+    if let Err(e) = pre_run::run_post(&project.post_run, &exit_env, project.capture_lines, verbose)
+    {
+        eprintln!(
+            "[rpy] Error: {:?}",
+            e.wrap_err("Unable to run post_run step")
+        );
+    }
+    if let Some(telemetry_log) = &project.telemetry_log {
+        let target = match cmdline_args.invocation_type() {
+            InvocationType::File(file) | InvocationType::Module(file) => Some(file.as_str()),
+            _ => None,
+        };
+        let record = telemetry::Record {
+            timestamp: telemetry::now(),
+            project_root: &project.root,
+            invocation: cmdline_args.invocation_type().kind(),
+            target,
+            interpreter: &launch.interpreter.path,
+            pre_run_ms,
+            script_ms,
+            exit_code: status.code(),
+            exit_signal: status.signal().map(supervise::signal_name),
+        };
+        if let Err(e) = telemetry::append(telemetry_log, &record) {
+            eprintln!("[rpy] Error: {e:?}");
         }
-        paths.insert(0, bin_path);
-        cmd.env("PATH", env::join_paths(paths).unwrap());
-    };
-
-    Err(Report::new(cmd.exec()))
+    }
+    supervise::exit_like(status)
 }
 
 fn main() {
     if let Err(e) = run() {
         eprintln!("[rpy] Error: {e:?}");
-        exit(1);
+        let failure = e
+            .chain()
+            .find_map(|err| err.downcast_ref::<pre_run::Failure>());
+        match failure.and_then(pre_run::Failure::signal) {
+            Some(signal) => supervise::die_from(signal),
+            None => exit(1),
+        }
     }
 }
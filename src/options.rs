@@ -0,0 +1,426 @@
+#![deny(warnings)]
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, eyre};
+
+/// Options aimed at rpy itself rather than python. These must all come before any python
+/// arguments and are spelled `--rpy-<name>`, which python will never accept itself.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub help: bool,
+    pub verbose: bool,
+    pub profile: Option<String>,
+    pub interpreter: Option<String>,
+    /// A directory to find the configuration from, instead of the script's or the current one.
+    pub project: Option<PathBuf>,
+    /// Extra environment variables for python, set after all the configured ones.
+    pub set: Vec<(String, String)>,
+    pub skip_pre_run: bool,
+    pub cache: Option<CacheCommand>,
+    pub explain: Option<Format>,
+    pub env: Option<Shell>,
+    pub shell: bool,
+    /// A file or directory to describe for editors, instead of running anything.
+    pub inspect: Option<PathBuf>,
+}
+
+/// How to print what rpy would do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// The shell to print activation statements for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// The shell named by `$SHELL`, or bash if that isn't one rpy knows.
+    fn from_env() -> Shell {
+        let shell = env::var("SHELL").unwrap_or_default();
+        match Path::new(&shell).file_name().and_then(|name| name.to_str()) {
+            Some("zsh") => Shell::Zsh,
+            Some("fish") => Shell::Fish,
+            _ => Shell::Bash,
+        }
+    }
+}
+
+/// What to do with rpy's cache, instead of running anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCommand {
+    List,
+    Clear,
+}
+
+const PREFIX: &str = "--rpy-";
+
+/// What `--rpy-help` prints.
+pub const HELP: &str = "\
+usage: rpy [rpy options] [python options and arguments]
+
+rpy runs python configured by the nearest pyproject.toml, rpy.toml or .rpy.toml. Its own
+options come first; everything from the first argument not starting with --rpy- goes to
+python untouched.
+
+options:
+  --rpy-help               print this help and exit
+  --rpy-verbose            report what rpy is doing (as RPY_VERBOSE=1)
+  --rpy-profile NAME       use [tool.rpy.profiles.NAME] (as RPY_PROFILE)
+  --rpy-interpreter PATH   run this interpreter (as RPY_INTERPRETER)
+  --rpy-project DIR        find the configuration from DIR instead
+  --rpy-set KEY=VALUE      set an environment variable for python (repeatable)
+  --rpy-skip-pre-run       don't run pre_run or [tool.rpy.env_manager] steps
+  --rpy-explain[=FORMAT]   print what would be run, as text or json, and exit
+  --rpy-env[=SHELL]        print statements activating the environment in bash, zsh or fish
+  --rpy-shell              start $SHELL in the environment instead of python
+  --rpy-inspect PATH       print how a file or directory would be run, as JSON, and exit
+  --rpy-cache list|clear   show or clear rpy's cache
+
+environment:
+  RPY_VERBOSE, RPY_PROFILE, RPY_INTERPRETER  as the options above
+  RPY_FORCE_PRE_RUN=1                       run pre_run steps even if their inputs are unchanged
+  RPY_IGNORE_REQUIRES_PYTHON=1              run an interpreter not matching requires-python
+";
+
+impl Options {
+    /// Removes any leading rpy options from `args`, leaving only the arguments destined for python.
+    pub fn parse(args: &mut Vec<String>) -> Result<Options> {
+        let mut options = Options::default();
+        while let Some(arg) = args.first() {
+            let Some(option) = arg.strip_prefix(PREFIX) else {
+                break;
+            };
+            let (name, inline_value) = match option.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (option.to_string(), None),
+            };
+            args.remove(0);
+            match name.as_str() {
+                "help" => options.help = Self::flag(&name, inline_value)?,
+                "verbose" => options.verbose = Self::flag(&name, inline_value)?,
+                "profile" => options.profile = Some(Self::value(&name, inline_value, args)?),
+                "interpreter" => {
+                    options.interpreter = Some(Self::value(&name, inline_value, args)?)
+                }
+                "project" => {
+                    options.project = Some(PathBuf::from(Self::value(&name, inline_value, args)?))
+                }
+                "set" => {
+                    let assignment = Self::value(&name, inline_value, args)?;
+                    let Some((key, value)) = assignment
+                        .split_once('=')
+                        .filter(|(key, _)| !key.is_empty())
+                    else {
+                        return Err(eyre!(
+                            "rpy option '{PREFIX}{name}' expects KEY=VALUE, not '{assignment}'"
+                        ));
+                    };
+                    options.set.push((key.to_string(), value.to_string()));
+                }
+                "skip-pre-run" => options.skip_pre_run = Self::flag(&name, inline_value)?,
+                "cache" => {
+                    options.cache = match Self::value(&name, inline_value, args)?.as_str() {
+                        "list" => Some(CacheCommand::List),
+                        "clear" => Some(CacheCommand::Clear),
+                        other => {
+                            return Err(eyre!(
+                                "Unknown cache command '{other}' (expected 'list' or 'clear')"
+                            ));
+                        }
+                    }
+                }
+                "explain" => {
+                    options.explain = match inline_value.as_deref() {
+                        None | Some("text") => Some(Format::Text),
+                        Some("json") => Some(Format::Json),
+                        Some(other) => {
+                            return Err(eyre!(
+                                "Unknown explain format '{other}' (expected 'text' or 'json')"
+                            ));
+                        }
+                    }
+                }
+                "env" => {
+                    options.env = match inline_value.as_deref() {
+                        None => Some(Shell::from_env()),
+                        Some("bash") => Some(Shell::Bash),
+                        Some("zsh") => Some(Shell::Zsh),
+                        Some("fish") => Some(Shell::Fish),
+                        Some(other) => {
+                            return Err(eyre!(
+                                "Unknown shell '{other}' (expected 'bash', 'zsh' or 'fish')"
+                            ));
+                        }
+                    }
+                }
+                "inspect" => {
+                    options.inspect = Some(PathBuf::from(Self::value(&name, inline_value, args)?))
+                }
+                "shell" => options.shell = Self::flag(&name, inline_value)?,
+                _ => return Err(eyre!("Unknown rpy option '{PREFIX}{name}'")),
+            }
+        }
+        let modes = [
+            ("explain", options.explain.is_some()),
+            ("env", options.env.is_some()),
+            ("shell", options.shell),
+            ("inspect", options.inspect.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, given)| *given)
+        .map(|(name, _)| format!("'{PREFIX}{name}'"))
+        .collect::<Vec<_>>();
+        if modes.len() > 1 {
+            return Err(eyre!(
+                "rpy options {} can't be used together",
+                modes.join(" and ")
+            ));
+        }
+        Ok(options)
+    }
+
+    fn flag(name: &str, inline_value: Option<String>) -> Result<bool> {
+        match inline_value {
+            Some(_) => Err(eyre!("rpy option '{PREFIX}{name}' takes no value")),
+            None => Ok(true),
+        }
+    }
+
+    fn value(name: &str, inline_value: Option<String>, args: &mut Vec<String>) -> Result<String> {
+        match inline_value {
+            Some(value) => Ok(value),
+            None if !args.is_empty() => Ok(args.remove(0)),
+            None => Err(eyre!("rpy option '{PREFIX}{name}' requires a value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheCommand, Format, Options, Shell};
+
+    fn parse(args: &[&str]) -> (Options, Vec<String>) {
+        let mut args = args.iter().map(|arg| arg.to_string()).collect();
+        let options = Options::parse(&mut args).unwrap();
+        (options, args)
+    }
+
+    #[test]
+    fn should_leave_python_args_alone() {
+        assert_eq!(
+            parse(&["-i", "--rpy-profile=debug", "script.py"]),
+            (
+                Options::default(),
+                vec![
+                    "-i".into(),
+                    "--rpy-profile=debug".into(),
+                    "script.py".into()
+                ]
+            )
+        );
+        assert_eq!(
+            parse(&["script.py", "--rpy-profile", "debug"]),
+            (
+                Options::default(),
+                vec!["script.py".into(), "--rpy-profile".into(), "debug".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn should_parse_profile() {
+        let expected = Options {
+            profile: Some("debug".into()),
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-profile=debug", "script.py"]),
+            (expected, vec!["script.py".into()])
+        );
+        let expected = Options {
+            profile: Some("debug".into()),
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-profile", "debug", "-m", "module"]),
+            (expected, vec!["-m".into(), "module".into()])
+        );
+    }
+
+    #[test]
+    fn should_parse_cache_commands() {
+        let expected = Options {
+            cache: Some(CacheCommand::List),
+            ..Options::default()
+        };
+        assert_eq!(parse(&["--rpy-cache", "list"]), (expected, vec![]));
+        let expected = Options {
+            cache: Some(CacheCommand::Clear),
+            ..Options::default()
+        };
+        assert_eq!(parse(&["--rpy-cache=clear"]), (expected, vec![]));
+        let mut args = vec!["--rpy-cache=purge".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "Unknown cache command 'purge' (expected 'list' or 'clear')"
+        );
+    }
+
+    #[test]
+    fn should_parse_explain_without_taking_the_next_arg() {
+        let expected = Options {
+            explain: Some(Format::Text),
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-explain", "script.py"]),
+            (expected, vec!["script.py".into()])
+        );
+        let expected = Options {
+            explain: Some(Format::Json),
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-explain=json", "-m", "app"]),
+            (expected, vec!["-m".into(), "app".into()])
+        );
+        let mut args = vec!["--rpy-explain=yaml".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "Unknown explain format 'yaml' (expected 'text' or 'json')"
+        );
+    }
+
+    #[test]
+    fn should_parse_env_shell() {
+        let expected = Options {
+            env: Some(Shell::Fish),
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-env=fish", "tool.py"]),
+            (expected, vec!["tool.py".into()])
+        );
+        let mut args = vec!["--rpy-env=csh".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "Unknown shell 'csh' (expected 'bash', 'zsh' or 'fish')"
+        );
+    }
+
+    #[test]
+    fn should_parse_shell_flag() {
+        let expected = Options {
+            shell: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-shell", "tool.py"]),
+            (expected, vec!["tool.py".into()])
+        );
+        let mut args = vec!["--rpy-shell=zsh".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy option '--rpy-shell' takes no value"
+        );
+    }
+
+    #[test]
+    fn should_parse_run_options() {
+        let expected = Options {
+            verbose: true,
+            interpreter: Some("/opt/python3.13/bin/python3".into()),
+            project: Some("../service".into()),
+            set: vec![("A".into(), "1".into()), ("B".into(), "x=y".into())],
+            skip_pre_run: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&[
+                "--rpy-verbose",
+                "--rpy-interpreter",
+                "/opt/python3.13/bin/python3",
+                "--rpy-project=../service",
+                "--rpy-set",
+                "A=1",
+                "--rpy-set=B=x=y",
+                "--rpy-skip-pre-run",
+                "-X",
+                "dev",
+                "--rpy-verbose",
+            ]),
+            (
+                expected,
+                vec!["-X".into(), "dev".into(), "--rpy-verbose".into()]
+            )
+        );
+        let mut args = vec!["--rpy-set".to_string(), "A".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy option '--rpy-set' expects KEY=VALUE, not 'A'"
+        );
+        let mut args = vec!["--rpy-set".to_string(), "=x".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy option '--rpy-set' expects KEY=VALUE, not '=x'"
+        );
+        let mut args = vec!["--rpy-verbose=1".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy option '--rpy-verbose' takes no value"
+        );
+    }
+
+    #[test]
+    fn should_reject_more_than_one_mode() {
+        let mut args = vec!["--rpy-explain".to_string(), "--rpy-shell".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy options '--rpy-explain' and '--rpy-shell' can't be used together"
+        );
+        let mut args = vec![
+            "--rpy-env=bash".to_string(),
+            "--rpy-inspect".to_string(),
+            ".".to_string(),
+            "--rpy-explain=json".to_string(),
+        ];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy options '--rpy-explain' and '--rpy-env' and '--rpy-inspect' can't be used together"
+        );
+    }
+
+    #[test]
+    fn should_parse_help() {
+        let expected = Options {
+            help: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            parse(&["--rpy-help", "--help"]),
+            (expected, vec!["--help".into()])
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_and_incomplete_options() {
+        let mut args = vec!["--rpy-badger".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "Unknown rpy option '--rpy-badger'"
+        );
+        let mut args = vec!["--rpy-profile".to_string()];
+        assert_eq!(
+            Options::parse(&mut args).unwrap_err().to_string(),
+            "rpy option '--rpy-profile' requires a value"
+        );
+    }
+}
@@ -0,0 +1,608 @@
+#![deny(warnings)]
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use std::{env, fs};
+
+use eyre::{ContextCompat, Result, WrapErr, eyre};
+
+use crate::cache::{self, InterpreterInfo};
+use crate::config::{CommandLine, Config, EntryPoint, EnvManager, OneOrMany, PreRun, Settings};
+use crate::environment::{Environment, Interpolation};
+use crate::interpreter::{self, Interpreter};
+use crate::options::Options;
+use crate::pre_run::{self, Hook, Step};
+use crate::rpy::{InvocationType, Rpy};
+use crate::version::{Specifiers, Version};
+use crate::{dotenv, env_manager, script_metadata};
+
+/// What rpy has worked out about the project from the command line and its configuration,
+/// before running anything.
+pub struct Project {
+    /// The files the configuration came from: the project's config files, or the script itself
+    /// when configured by inline metadata.
+    pub config_files: Vec<PathBuf>,
+    pub inline_metadata: bool,
+    pub root: PathBuf,
+    pub profile: Option<String>,
+    requires_python: Option<Specifiers>,
+    settings: Settings,
+    raw_interpreter: Option<(Vec<String>, &'static str)>,
+    managed_python: Option<PathBuf>,
+    python_path: Vec<PathBuf>,
+    /// The environment manager's steps followed by the pre_run steps.
+    pub pre_run: Vec<Step>,
+    /// Where pre_run keeps its lock and stamps, instead of the project root and the cache's
+    /// pre_run directory: a script_env's cache directory, so running a standalone script leaves
+    /// nothing beside it.
+    pub pre_run_dir: Option<PathBuf>,
+    pub lock_timeout: Duration,
+    pub capture_lines: usize,
+    pub post_run: Vec<Step>,
+    pub supervise: bool,
+    pub telemetry_log: Option<PathBuf>,
+    /// Variables from `--rpy-set`.
+    extra_env: Vec<(String, String)>,
+}
+
+/// How python is to be run.
+pub struct Launch {
+    pub interpreter: Interpreter,
+    /// What was learned about the interpreter by checking it against `requires-python`.
+    pub info: Option<InterpreterInfo>,
+    pub args: Vec<String>,
+    pub environment: Environment,
+}
+
+/// How an interpreter compares with `requires-python`.
+pub struct PythonCheck {
+    pub info: InterpreterInfo,
+    pub requires_python: String,
+    pub satisfied: bool,
+}
+
+impl Launch {
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.interpreter.path);
+        cmd.args(&self.args);
+        self.environment.apply(&mut cmd);
+        cmd
+    }
+}
+
+impl Project {
+    /// Finds and resolves the configuration for `cmdline_args`, as adjusted by `options`.
+    pub fn load(cmdline_args: &Rpy, options: &Options, verbose: bool) -> Result<Project> {
+        let profile = options
+            .profile
+            .clone()
+            .or_else(|| env::var("RPY_PROFILE").ok().filter(|p| !p.is_empty()));
+        let (config_files, inline_metadata) = match &options.project {
+            Some(dir) => (Rpy::find_config_from(dir)?, None),
+            None => match cmdline_args.find_config() {
+                Ok(files) => (files, None),
+                Err(err) => match inline_script_metadata(cmdline_args.invocation_type())? {
+                    Some((script, metadata)) => (vec![script], Some(metadata)),
+                    None => return Err(err),
+                },
+            },
+        };
+        let config_path = &config_files[0];
+        let project_root = config_path
+            .parent()
+            .wrap_err("Unable to get project root")?;
+        if verbose {
+            println!("project root: {}", project_root.display());
+            match inline_metadata {
+                Some(_) => println!("script metadata: {}", config_path.display()),
+                None => {
+                    for file in &config_files {
+                        println!("config: {}", file.display());
+                    }
+                }
+            }
+        }
+        if verbose && let Some(profile) = &profile {
+            println!("profile: {profile}");
+        }
+        let script = match cmdline_args.invocation_type() {
+            InvocationType::File(filename) => Some(
+                fs::canonicalize(filename)
+                    .wrap_err(format!("Failed to canonicalize \"{filename}\""))?,
+            ),
+            _ => None,
+        };
+        let entry_point = match (cmdline_args.invocation_type(), &script) {
+            (InvocationType::Module(module), _) => EntryPoint::Module(module),
+            (_, Some(script)) => script
+                .strip_prefix(project_root)
+                .map_or(EntryPoint::Other, EntryPoint::Script),
+            _ => EntryPoint::Other,
+        };
+        let config = match &inline_metadata {
+            Some(metadata) => Config::from_script_metadata(metadata)
+                .wrap_err(format!("Unable to configure {}", config_path.display()))?,
+            None => Config::load(&config_files)?,
+        };
+        let dependencies = config.dependencies().to_vec();
+        let requires_python = config
+            .requires_python()
+            .map(Specifiers::parse)
+            .transpose()
+            .wrap_err("Unable to parse requires-python")?;
+        let mut settings = config.resolve(entry_point, profile.as_deref())?;
+        let mut standalone_env = None;
+        if inline_metadata.is_some() && settings.script_env.unwrap_or(false) {
+            if settings.env_manager.is_some() || settings.interpreter.is_some() {
+                return Err(eyre!(
+                    "script_env provides the interpreter, so can't be used with interpreter or \
+                     [tool.rpy.env_manager]"
+                ));
+            }
+            let env = script_env(config_path, &dependencies)?;
+            settings.env_manager = Some(env.manager.clone());
+            standalone_env = Some(env);
+        }
+        if settings.env_manager.is_some() && settings.interpreter.is_some() {
+            return Err(eyre!(
+                "Set either interpreter or [tool.rpy.env_manager], not both: the environment \
+                 manager provides the interpreter"
+            ));
+        }
+        let raw_interpreter = match (&options.interpreter, env::var("RPY_INTERPRETER")) {
+            (Some(interpreter), _) => Some((vec![interpreter.clone()], "--rpy-interpreter")),
+            (None, Ok(interpreter)) => Some((vec![interpreter], "RPY_INTERPRETER")),
+            (None, Err(_)) => settings
+                .interpreter
+                .take()
+                .map(|interpreter| (interpreter.into_vec(), "tool.rpy.interpreter")),
+        };
+        let source_roots = match settings.source_root.take() {
+            Some(roots) => roots.into_vec(),
+            None => vec![String::new()],
+        };
+        let python_path = source_roots
+            .iter()
+            .map(|root| project_root.join(Path::new(root)))
+            .collect::<Vec<_>>();
+        let interpolation = Interpolation {
+            project_root,
+            source_root: python_path.first().map_or(project_root, PathBuf::as_path),
+        };
+        let post_run = match settings.post_run.take() {
+            Some(post_run) => hook_steps(
+                Hook::PostRun,
+                project_root,
+                &interpolation,
+                post_run,
+                settings.pre_run_shell.as_deref(),
+                None,
+            )?,
+            None => vec![],
+        };
+        let telemetry_log = match &settings.telemetry_log {
+            Some(path) => Some(
+                project_root.join(
+                    interpolation
+                        .expand(path)
+                        .wrap_err("Unable to expand telemetry_log")?,
+                ),
+            ),
+            None => None,
+        };
+        let supervise =
+            settings.supervise.unwrap_or(false) || !post_run.is_empty() || telemetry_log.is_some();
+        let mut pre_run = vec![];
+        let mut managed_python = None;
+        if let Some(manager) = &settings.env_manager {
+            let requirements = standalone_env.as_ref().map(|env| env.requirements.clone());
+            let (manager_steps, python) = env_manager::steps(project_root, manager, requirements)?;
+            pre_run.extend(manager_steps);
+            managed_python = Some(python);
+        }
+        if let Some(steps) = settings.pre_run.take() {
+            pre_run.extend(hook_steps(
+                Hook::PreRun,
+                project_root,
+                &interpolation,
+                steps,
+                settings.pre_run_shell.as_deref(),
+                settings.pre_run_inputs.as_deref(),
+            )?);
+        }
+        if options.skip_pre_run && !pre_run.is_empty() {
+            if verbose {
+                println!("pre_run: skipping {} step(s)", pre_run.len());
+            }
+            pre_run.clear();
+        }
+        Ok(Project {
+            config_files: config_files.clone(),
+            inline_metadata: inline_metadata.is_some(),
+            root: project_root.to_path_buf(),
+            profile,
+            requires_python,
+            raw_interpreter,
+            managed_python,
+            python_path,
+            pre_run,
+            pre_run_dir: standalone_env.map(|env| env.dir),
+            lock_timeout: settings
+                .pre_run_lock_timeout
+                .map_or(pre_run::DEFAULT_LOCK_TIMEOUT, Duration::from_secs),
+            capture_lines: settings.pre_run_capture_lines.unwrap_or(0),
+            post_run,
+            supervise,
+            telemetry_log,
+            extra_env: options.set.clone(),
+            settings,
+        })
+    }
+
+    fn interpolation(&self) -> Interpolation<'_> {
+        Interpolation {
+            project_root: &self.root,
+            source_root: self
+                .python_path
+                .first()
+                .map_or(&self.root, PathBuf::as_path),
+        }
+    }
+
+    /// Chooses the interpreter and builds the environment to run it with. This happens after
+    /// pre_run, which may well create the interpreter.
+    pub fn launch(&self, cmdline_args: &Rpy, verbose: bool) -> Result<Launch> {
+        let interpreter = self.interpreter()?;
+        let check = self.check_requires_python(&interpreter, verbose)?;
+        if let Some(check) = &check
+            && !check.satisfied
+        {
+            return Err(eyre!(
+                "{} is python {}, which does not satisfy requires-python '{}' from {} (set \
+                 RPY_IGNORE_REQUIRES_PYTHON=1 to run it anyway)",
+                interpreter.path.display(),
+                check.info.version,
+                check.requires_python,
+                self.config_files[0].display()
+            ));
+        }
+        if verbose {
+            println!(
+                "python: {} (from {})",
+                interpreter.path.display(),
+                interpreter.source
+            );
+            for src_root in &self.python_path {
+                println!("src_root: {}", src_root.display());
+            }
+        }
+        Ok(Launch {
+            interpreter,
+            info: check.map(|check| check.info),
+            args: cmdline_args.make_args().into_iter().cloned().collect(),
+            environment: self.environment(verbose)?,
+        })
+    }
+
+    /// Chooses the interpreter, without running it.
+    pub fn interpreter(&self) -> Result<Interpreter> {
+        let project_root = self.root.as_path();
+        Ok(match &self.raw_interpreter {
+            Some((candidates, source)) => {
+                let (path, index) = interpreter::first_usable(
+                    project_root,
+                    candidates,
+                    self.search_path()?.as_deref(),
+                )?;
+                Interpreter {
+                    path,
+                    source: if candidates.len() > 1 {
+                        format!("{source}[{index}]")
+                    } else {
+                        source.to_string()
+                    },
+                }
+            }
+            None => match &self.managed_python {
+                Some(path) => Interpreter {
+                    path: path.clone(),
+                    source: "tool.rpy.env_manager".to_string(),
+                },
+                None => interpreter::discover(project_root, self.requires_python.as_ref())?,
+            },
+        })
+    }
+
+    /// The `PATH` python will be run with, to find interpreters given by name on.
+    pub fn search_path(&self) -> Result<Option<OsString>> {
+        Ok(self.environment(false)?.get("PATH"))
+    }
+
+    /// The interpreters `interpreter` chooses from: those configured (or from
+    /// `RPY_INTERPRETER`), or the environment manager's. Empty when it is discovered instead.
+    pub fn interpreter_candidates(&self) -> Vec<String> {
+        match (&self.raw_interpreter, &self.managed_python) {
+            (Some((candidates, _)), _) => candidates.clone(),
+            (None, Some(path)) => vec![path.to_string_lossy().to_string()],
+            (None, None) => vec![],
+        }
+    }
+
+    /// Checks `interpreter` against `requires-python`, running it if it isn't cached. `None` when
+    /// there is no `requires-python`, or `RPY_IGNORE_REQUIRES_PYTHON` is set.
+    pub fn check_requires_python(
+        &self,
+        interpreter: &Interpreter,
+        verbose: bool,
+    ) -> Result<Option<PythonCheck>> {
+        let Some(requires_python) = &self.requires_python else {
+            return Ok(None);
+        };
+        if env::var("RPY_IGNORE_REQUIRES_PYTHON").is_ok_and(|x| x != "0") {
+            return Ok(None);
+        }
+        let info = interpreter::info(&interpreter.path, self.search_path()?.as_deref())?;
+        let version = Version::parse(&info.version)?;
+        if verbose {
+            println!("python version: {version} (requires-python {requires_python})");
+            println!("python prefix: {}", info.prefix);
+            println!("python platform: {}", info.platform);
+        }
+        Ok(Some(PythonCheck {
+            satisfied: requires_python.contains(&version),
+            requires_python: requires_python.to_string(),
+            info,
+        }))
+    }
+
+    /// The directories python's `PYTHONPATH` starts with.
+    pub fn source_roots(&self) -> &[PathBuf] {
+        &self.python_path
+    }
+
+    /// The configured `bin_path`, canonicalized if it exists. If it doesn't (perhaps until
+    /// pre_run creates it), it is returned as configured along with why it can't be used.
+    pub fn bin_path(&self) -> Option<(PathBuf, Option<String>)> {
+        let configured = self.root.join(self.settings.bin_path.as_ref()?);
+        Some(match configured.canonicalize() {
+            Ok(bin_path) => (bin_path, None),
+            Err(e) => {
+                let error = format!("Unable to canonicalize {}: {e}", configured.display());
+                (configured, Some(error))
+            }
+        })
+    }
+
+    /// The changes to make to the environment python is run with.
+    pub fn environment(&self, verbose: bool) -> Result<Environment> {
+        let project_root = self.root.as_path();
+        let settings = &self.settings;
+        let interpolation = self.interpolation();
+        let mut environment = Environment::default();
+        for dotenv_file in settings.dotenv.iter().flat_map(OneOrMany::iter) {
+            let dotenv_path = project_root.join(dotenv_file);
+            if !dotenv_path.exists() {
+                if verbose {
+                    println!("dotenv: {} (not found)", dotenv_path.display());
+                }
+                continue;
+            }
+            if verbose {
+                println!("dotenv: {}", dotenv_path.display());
+            }
+            for (name, value) in dotenv::load(&dotenv_path)? {
+                environment.set(&name, value);
+            }
+        }
+        let mut inherited_python_path = vec![];
+        if settings.inherit_pythonpath.unwrap_or(false)
+            && let Some(inherited) = environment.get("PYTHONPATH")
+        {
+            inherited_python_path
+                .extend(env::split_paths(&inherited).filter(|p| !p.as_os_str().is_empty()));
+        }
+        environment.set(
+            "PYTHONPATH",
+            env::join_paths(self.python_path.iter().chain(&inherited_python_path))
+                .wrap_err("Unable to build PYTHONPATH from source roots")?,
+        );
+        for (name, enabled) in [
+            ("PYTHONNOUSERSITE", settings.no_user_site.unwrap_or(true)),
+            ("PYTHONSAFEPATH", settings.safe_path.unwrap_or(true)),
+        ] {
+            if enabled {
+                environment.set(name, "1");
+            } else {
+                environment.remove(name);
+            }
+        }
+        environment.remove("RPY_INTERPRETER");
+        environment.remove("RPY_PROFILE");
+        environment.remove("RPY_IGNORE_REQUIRES_PYTHON");
+
+        if let Some((bin_path, error)) = self.bin_path() {
+            if verbose {
+                match error {
+                    Some(error) => println!("bin_path: {} ({error})", bin_path.display()),
+                    None => println!("bin_path: {}", bin_path.display()),
+                }
+            }
+            environment.prepend_paths("PATH", vec![bin_path])?;
+        };
+
+        for (name, value) in settings.env.iter().flatten() {
+            let value = interpolation
+                .expand(value)
+                .wrap_err(format!("Unable to expand env.{name}"))?;
+            if verbose {
+                println!("env: {name}={value}");
+            }
+            environment.set(name, value);
+        }
+        for (name, values) in settings.env_prepend.iter().flatten() {
+            let paths = expand_paths(&interpolation, name, values)?;
+            environment.prepend_paths(name, paths)?;
+        }
+        for (name, values) in settings.env_append.iter().flatten() {
+            let paths = expand_paths(&interpolation, name, values)?;
+            environment.append_paths(name, paths)?;
+        }
+        for name in settings.env_remove.iter().flatten() {
+            environment.remove(name);
+        }
+        for (name, value) in &self.extra_env {
+            if verbose {
+                println!("env: {name}={value} (from --rpy-set)");
+            }
+            environment.set(name, value);
+        }
+        if verbose {
+            for name in ["PYTHONNOUSERSITE", "PYTHONSAFEPATH"] {
+                match environment.get(name) {
+                    Some(value) => println!("{name}: {}", value.to_string_lossy()),
+                    None => println!("{name}: not set"),
+                }
+            }
+        }
+        Ok(environment)
+    }
+}
+
+fn expand_paths(
+    interpolation: &Interpolation,
+    name: &str,
+    values: &OneOrMany<String>,
+) -> Result<Vec<PathBuf>> {
+    values
+        .iter()
+        .map(|value| {
+            interpolation
+                .expand(value)
+                .map(PathBuf::from)
+                .wrap_err(format!("Unable to expand paths for {name}"))
+        })
+        .collect()
+}
+
+fn hook_steps(
+    hook: Hook,
+    project_root: &Path,
+    interpolation: &Interpolation,
+    steps: PreRun,
+    shell: Option<&[String]>,
+    inputs: Option<&[String]>,
+) -> Result<Vec<Step>> {
+    let shell = shell.map_or_else(
+        || pre_run::DEFAULT_SHELL.map(String::from).to_vec(),
+        <[_]>::to_vec,
+    );
+    if shell.is_empty() {
+        return Err(eyre!("pre_run_shell must not be empty"));
+    }
+    let argv = |cmd: &CommandLine| match cmd {
+        CommandLine::Shell(cmd) => (shell.iter().cloned().chain([cmd.clone()]).collect(), true),
+        CommandLine::Argv(argv) => (argv.clone(), false),
+    };
+    let inputs = inputs.map(<[_]>::to_vec);
+    match steps {
+        PreRun::Command(cmd) => {
+            let (argv, via_shell) = argv(&cmd);
+            Ok(vec![Step {
+                hook,
+                name: cmd.to_string(),
+                cmd: cmd.to_string(),
+                argv,
+                via_shell,
+                cwd: project_root.to_path_buf(),
+                env: vec![],
+                inputs,
+                writes: None,
+            }])
+        }
+        PreRun::Steps(steps) => steps
+            .into_iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let name = step
+                    .name
+                    .unwrap_or_else(|| format!("{}[{index}]", hook.setting()));
+                let env = step
+                    .env
+                    .into_iter()
+                    .map(|(var, value)| {
+                        let value = interpolation
+                            .expand(&value)
+                            .wrap_err(format!("Unable to expand env.{var} of step '{name}'"))?;
+                        Ok((var, value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let (argv, via_shell) = argv(&step.cmd);
+                Ok(Step {
+                    hook,
+                    cmd: step.cmd.to_string(),
+                    argv,
+                    via_shell,
+                    cwd: project_root.join(step.cwd.unwrap_or_default()),
+                    env,
+                    inputs: step.inputs.or_else(|| inputs.clone()),
+                    writes: None,
+                    name,
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Finds the PEP 723 `# /// script` metadata of the script being run, to configure it when it
+/// isn't part of a project.
+fn inline_script_metadata(invocation: &InvocationType) -> Result<Option<(PathBuf, String)>> {
+    let InvocationType::File(filename) = invocation else {
+        return Ok(None);
+    };
+    let Ok(script) = fs::canonicalize(filename) else {
+        return Ok(None);
+    };
+    let Ok(source) = fs::read_to_string(&script) else {
+        return Ok(None);
+    };
+    let metadata = script_metadata::parse(&source).wrap_err(format!(
+        "Unable to read the inline script metadata of {}",
+        script.display()
+    ))?;
+    Ok(metadata.map(|metadata| (script, metadata)))
+}
+
+/// An environment in the cache for a standalone script, holding the dependencies its inline
+/// metadata declares.
+struct ScriptEnv {
+    manager: EnvManager,
+    /// The cache directory holding the environment and its requirements.
+    dir: PathBuf,
+    /// The requirements, which the sync step writes to the manager's lockfile.
+    requirements: String,
+}
+
+fn script_env(script: &Path, dependencies: &[String]) -> Result<ScriptEnv> {
+    let dir = cache::dir()
+        .wrap_err("Unable to find a cache directory for script_env")?
+        .join("scripts")
+        .join(cache::entry_name(&script.to_string_lossy()));
+    let manager = EnvManager {
+        lockfile: dir.join("requirements.txt").to_string_lossy().to_string(),
+        env_dir: Some(dir.join("env").to_string_lossy().to_string()),
+        tool: None,
+        python: None,
+        wheelhouse: None,
+    };
+    let requirements = dependencies
+        .iter()
+        .map(|dependency| format!("{dependency}\n"))
+        .collect();
+    Ok(ScriptEnv {
+        manager,
+        dir,
+        requirements,
+    })
+}
@@ -0,0 +1,424 @@
+#![deny(warnings)]
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eyre::{Result, WrapErr, eyre};
+use glob::{Pattern, glob};
+
+use crate::cache::{self, Fnv};
+use crate::supervise::{IgnoringInterrupts, signal_name};
+
+/// Whether a step runs before the interpreter, or after it in supervised mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreRun,
+    PostRun,
+}
+
+impl Hook {
+    /// The name of the setting the step comes from.
+    pub fn setting(self) -> &'static str {
+        match self {
+            Hook::PreRun => "pre_run",
+            Hook::PostRun => "post_run",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Hook::PreRun => "Pre-run",
+            Hook::PostRun => "Post-run",
+        }
+    }
+}
+
+/// A single pre_run or post_run step, ready to run.
+#[derive(Debug)]
+pub struct Step {
+    pub hook: Hook,
+    pub name: String,
+    /// The command as written in the configuration, for reporting.
+    pub cmd: String,
+    /// What to actually run: the shell and its arguments followed by `cmd`, or the command's own
+    /// argv when it is run without a shell.
+    pub argv: Vec<String>,
+    pub via_shell: bool,
+    pub cwd: PathBuf,
+    pub env: Vec<(String, String)>,
+    pub inputs: Option<Vec<String>>,
+    /// A file to write before running the step, and its contents: a script_env's requirements,
+    /// which only exist in the script's metadata until then.
+    pub writes: Option<(PathBuf, String)>,
+}
+
+/// The shell pre_run commands are given to, unless `pre_run_shell` says otherwise.
+pub const DEFAULT_SHELL: [&str; 5] = ["bash", "-eu", "-o", "pipefail", "-c"];
+
+impl Step {
+    /// Runs the step with `env` added to its environment and its output sent to rpy's stderr,
+    /// keeping the last `capture_lines` lines of it (stdout and stderr interleaved) for the error
+    /// if the step fails. rpy ignores interrupts from the terminal until the step has finished.
+    fn execute(&self, env: &[(String, String)], capture_lines: usize, verbose: bool) -> Result<()> {
+        if verbose {
+            let hook = self.hook.setting();
+            if self.name == self.cmd {
+                println!("running {hook}: {}", self.cmd);
+            } else {
+                println!("running {hook} step '{}': {}", self.name, self.cmd);
+            }
+        }
+        let (program, args) = self.argv.split_first().ok_or_else(|| {
+            eyre!(
+                "{} step '{}' has an empty command",
+                self.hook.title(),
+                self.name
+            )
+        })?;
+        if let Some((path, contents)) = &self.writes {
+            cache::write(path, contents).wrap_err(format!("Unable to write {}", path.display()))?;
+        }
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .envs(
+                env.iter()
+                    .chain(&self.env)
+                    .map(|(name, value)| (name, value)),
+            )
+            .current_dir(&self.cwd);
+        let reader = if capture_lines > 0 {
+            let (reader, writer) = os_pipe::pipe()?;
+            command.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        } else {
+            command
+                .stdout(os_pipe::dup_stderr()?)
+                .stderr(Stdio::inherit());
+            None
+        };
+        let _ignoring = IgnoringInterrupts::new(&mut command);
+        let mut child = command.spawn().map_err(|err| match err.kind() {
+            ErrorKind::NotFound if self.via_shell => eyre!(
+                "The pre_run shell '{program}' was not found; set pre_run_shell to use \
+                 another, e.g. pre_run_shell = [\"sh\", \"-c\"]"
+            ),
+            ErrorKind::NotFound => eyre!(
+                "The command '{program}' of pre_run step '{}' was not found",
+                self.name
+            ),
+            _ => eyre!(
+                "Unable to run pre_run step '{}' ({program}): {err}",
+                self.name
+            ),
+        })?;
+        // The command holds the pipe's write ends; they must be closed to see the end of the output.
+        drop(command);
+        let mut output = VecDeque::new();
+        if let Some(reader) = reader {
+            let mut stderr = std::io::stderr();
+            for line in BufReader::new(reader).split(b'\n') {
+                let line = line?;
+                stderr.write_all(&line)?;
+                stderr.write_all(b"\n")?;
+                if output.len() == capture_lines {
+                    output.pop_front();
+                }
+                output.push_back(String::from_utf8_lossy(&line).into_owned());
+            }
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Failure {
+                hook: self.hook,
+                step: self.name.clone(),
+                status,
+                output: output.into(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn stamp(&self, project_root: &Path, dir: Option<&Path>) -> Option<Stamp> {
+        let inputs = self.inputs.as_ref()?;
+        Some(Stamp::new(project_root, dir, self, inputs))
+    }
+}
+
+/// A pre_run or post_run step that ran but did not succeed.
+#[derive(Debug)]
+pub struct Failure {
+    hook: Hook,
+    step: String,
+    status: ExitStatus,
+    output: Vec<String>,
+}
+
+impl Failure {
+    /// The signal that killed the step, which rpy dies from in turn after reporting the failure
+    /// so that an interrupted rpy looks interrupted to whoever started it.
+    pub fn signal(&self) -> Option<i32> {
+        self.status.signal()
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = self.hook.title();
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _) => write!(
+                f,
+                "{title} step '{}' failed with exit code {code}",
+                self.step
+            )?,
+            (None, Some(signal)) => write!(
+                f,
+                "{title} step '{}' was killed by signal {} ({signal})",
+                self.step,
+                signal_name(signal)
+            )?,
+            (None, None) => write!(f, "{title} step '{}' failed: {}", self.step, self.status)?,
+        }
+        if !self.output.is_empty() {
+            write!(f, "\nLast {} lines of its output:", self.output.len())?;
+            for line in &self.output {
+                write!(f, "\n  {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// How long to wait for another rpy to finish its pre_run by default.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+const LOCK_FILE: &str = ".rpy-pre-run.lock";
+
+fn forced() -> bool {
+    std::env::var("RPY_FORCE_PRE_RUN").is_ok_and(|x| x != "0")
+}
+
+/// Runs the pre_run `steps` in order. A step with `inputs` is skipped when neither it nor the
+/// files matching its input globs have changed since it last succeeded, unless
+/// `RPY_FORCE_PRE_RUN` is set.
+///
+/// Steps run holding an exclusive lock on a file in the project root, so concurrent rpy
+/// invocations in the same project wait for each other rather than racing. Once a waiting rpy
+/// gets the lock, it checks the inputs again as the steps will usually have just been run. With
+/// `dir`, the lock and the record of each step's inputs are kept there instead.
+pub fn run(
+    project_root: &Path,
+    dir: Option<&Path>,
+    steps: &[Step],
+    lock_timeout: Duration,
+    capture_lines: usize,
+    verbose: bool,
+) -> Result<()> {
+    let forced = forced();
+    let stamps = steps
+        .iter()
+        .map(|step| step.stamp(project_root, dir))
+        .collect::<Vec<_>>();
+    let is_current = |stamp: &Option<Stamp>| -> Result<bool> {
+        match stamp {
+            Some(stamp) if !forced => stamp.is_current(),
+            _ => Ok(false),
+        }
+    };
+    let mut all_current = true;
+    for stamp in &stamps {
+        all_current &= is_current(stamp)?;
+    }
+    if all_current {
+        if verbose {
+            for step in steps {
+                println!("skipping pre_run, inputs unchanged: {}", step.name);
+            }
+        }
+        return Ok(());
+    }
+    let _lock = lock(dir.unwrap_or(project_root), lock_timeout, verbose)?;
+    for (step, stamp) in steps.iter().zip(&stamps) {
+        if is_current(stamp)? {
+            if verbose {
+                println!("skipping pre_run, inputs unchanged: {}", step.name);
+            }
+            continue;
+        }
+        step.execute(&[], capture_lines, verbose)?;
+        if let Some(stamp) = stamp {
+            stamp.record()?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether each of `steps` would run now, or be skipped because its inputs are unchanged.
+pub fn pending(project_root: &Path, dir: Option<&Path>, steps: &[Step]) -> Result<Vec<bool>> {
+    let forced = forced();
+    steps
+        .iter()
+        .map(|step| match step.stamp(project_root, dir) {
+            Some(stamp) if !forced => Ok(!stamp.is_current()?),
+            _ => Ok(true),
+        })
+        .collect()
+}
+
+/// Runs the post_run `steps` in order, with `env` added to each step's environment. Unlike
+/// pre_run steps these always run, and without the lock.
+pub fn run_post(
+    steps: &[Step],
+    env: &[(String, String)],
+    capture_lines: usize,
+    verbose: bool,
+) -> Result<()> {
+    for step in steps {
+        step.execute(env, capture_lines, verbose)?;
+    }
+    Ok(())
+}
+
+/// Takes the pre_run lock in `dir`, waiting up to `timeout` for another process to release it.
+/// The lock is released when the returned file is closed.
+fn lock(dir: &Path, timeout: Duration, verbose: bool) -> Result<File> {
+    let path = dir.join(LOCK_FILE);
+    fs::create_dir_all(dir).wrap_err(format!("Unable to create {}", dir.display()))?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .wrap_err(format!(
+            "Unable to open pre_run lock file {}",
+            path.display()
+        ))?;
+    let start = Instant::now();
+    let mut reported = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => {
+                return Err(err).wrap_err(format!("Unable to lock {}", path.display()));
+            }
+        }
+        let holder = fs::read_to_string(&path).unwrap_or_default();
+        let holder = match holder.trim() {
+            "" => "another process".to_string(),
+            pid => format!("pid {pid}"),
+        };
+        if start.elapsed() >= timeout {
+            return Err(eyre!(
+                "Timed out after {}s waiting for the pre_run lock {} held by {holder}",
+                timeout.as_secs(),
+                path.display()
+            ));
+        }
+        if verbose && !reported {
+            println!(
+                "waiting for pre_run lock {} held by {holder}",
+                path.display()
+            );
+            reported = true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    file.set_len(0)?;
+    writeln!(file, "{}", process::id())?;
+    Ok(file)
+}
+
+/// Records the state of a pre_run step's inputs when it last succeeded, in the cache directory
+/// unless given another.
+struct Stamp {
+    project_root: PathBuf,
+    step: String,
+    inputs: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl Stamp {
+    fn new(project_root: &Path, dir: Option<&Path>, step: &Step, inputs: &[String]) -> Stamp {
+        let key = format!(
+            "{}\0{}\0{}",
+            project_root.display(),
+            step.cwd.display(),
+            step.cmd
+        );
+        let mut identity = format!("{:?}\0{:?}", step.argv, step.env);
+        if let Some((_, contents)) = &step.writes {
+            identity.push_str(&format!("\0{contents:?}"));
+        }
+        let dir = dir.map(Path::to_path_buf).or_else(cache::dir);
+        Stamp {
+            project_root: project_root.to_path_buf(),
+            step: identity,
+            inputs: inputs.to_vec(),
+            path: dir.map(|dir| dir.join("pre_run").join(cache::entry_name(&key))),
+        }
+    }
+
+    /// Hashes the step's command and environment, and the names and contents of every file
+    /// matching its inputs.
+    fn hash(&self) -> Result<String> {
+        let mut files = vec![];
+        for input in &self.inputs {
+            let pattern = if Path::new(input).is_absolute() {
+                input.clone()
+            } else {
+                let root = Pattern::escape(&self.project_root.to_string_lossy());
+                format!("{root}/{input}")
+            };
+            let matches =
+                glob(&pattern).wrap_err(format!("Invalid pre_run_inputs glob '{input}'"))?;
+            files.extend(
+                matches
+                    .filter_map(|path| path.ok())
+                    .filter(|path| path.is_file()),
+            );
+        }
+        files.sort();
+        files.dedup();
+        let mut hasher = Fnv::default();
+        hasher.write(self.step.as_bytes());
+        for file in files {
+            hasher.write(b"\0");
+            hasher.write(file.as_os_str().as_encoded_bytes());
+            hasher.write(b"\0");
+            hasher.write(&fs::read(&file).wrap_err(format!("Unable to read {}", file.display()))?);
+        }
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    fn is_current(&self) -> Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        match fs::read_to_string(path) {
+            Ok(recorded) => Ok(recorded.trim() == self.hash()?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).wrap_err(format!("Unable to read {}", path.display())),
+        }
+    }
+
+    /// Records the inputs as they are now: the pre_run step may well have changed them.
+    fn record(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            cache::write(path, &self.hash()?)
+                .wrap_err(format!("Unable to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
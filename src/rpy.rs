@@ -5,6 +5,11 @@ use std::{env, fs};
 
 use eyre::{ContextCompat, Result, WrapErr, eyre};
 
+/// The files rpy takes its configuration from, in increasing priority: `[tool.rpy]` in
+/// `pyproject.toml`, then the top level of `rpy.toml`, then `.rpy.toml` for uncommitted
+/// local changes.
+pub const CONFIG_FILES: [&str; 3] = ["pyproject.toml", "rpy.toml", ".rpy.toml"];
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum InvocationType {
     Interactive,
@@ -13,6 +18,18 @@ pub enum InvocationType {
     File(String),
 }
 
+impl InvocationType {
+    /// The name of the variant, e.g. `File`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InvocationType::Interactive => "Interactive",
+            InvocationType::Module(_) => "Module",
+            InvocationType::Command(_) => "Command",
+            InvocationType::File(_) => "File",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Rpy {
     python_args: Vec<String>,
@@ -114,18 +131,25 @@ impl Rpy {
         }
     }
 
-    fn find_toml_for_path(path: &Path) -> Option<PathBuf> {
-        let toml = Path::new("pyproject.toml");
-        let toml_path = path.join(toml);
-        if toml_path.is_file() {
-            return Some(toml_path);
+    fn find_config_for_path(path: &Path) -> Option<Vec<PathBuf>> {
+        let found = CONFIG_FILES
+            .iter()
+            .map(|name| path.join(name))
+            .filter(|config| config.is_file())
+            .collect::<Vec<_>>();
+        if !found.is_empty() {
+            return Some(found);
         }
         match path.parent() {
-            Some(path) => Self::find_toml_for_path(path),
+            Some(path) => Self::find_config_for_path(path),
             None => None,
         }
     }
-    pub fn find_toml(&self) -> Result<PathBuf> {
+
+    /// Finds the configuration files for the invocation: those in the nearest directory (from
+    /// the script, or the current directory) with any of `CONFIG_FILES`, in the order they
+    /// should be layered.
+    pub fn find_config(&self) -> Result<Vec<PathBuf>> {
         let path = match &self.invocation_type {
             InvocationType::Interactive
             | InvocationType::Module(_)
@@ -145,12 +169,28 @@ impl Rpy {
                     .to_path_buf()
             }
         };
-        Self::find_toml_for_path(&path).wrap_err(format!(
-            "Unable to find pyproject.toml from {}",
+        Self::find_config_for_path(&path).wrap_err(format!(
+            "Unable to find pyproject.toml, rpy.toml or .rpy.toml from {}",
             path.display()
         ))
     }
 
+    /// Finds the configuration files as `find_config` does, but starting from `dir`.
+    pub fn find_config_from(dir: &Path) -> Result<Vec<PathBuf>> {
+        let path = fs::canonicalize(dir).wrap_err(format!(
+            "Unable to find project directory {}",
+            dir.display()
+        ))?;
+        Self::find_config_for_path(&path).wrap_err(format!(
+            "Unable to find pyproject.toml, rpy.toml or .rpy.toml from {}",
+            path.display()
+        ))
+    }
+
+    pub fn invocation_type(&self) -> &InvocationType {
+        &self.invocation_type
+    }
+
     pub fn make_args(&self) -> Vec<&String> {
         let mut args = vec![];
         args.extend(&self.python_args[..]);
@@ -0,0 +1,93 @@
+#![deny(warnings)]
+
+use eyre::{Result, eyre};
+
+/// Extracts the TOML in the `script` block of PEP 723 inline script metadata, if `source` has
+/// one. A block starts with a `# /// script` line, continues with lines that are `#` or start
+/// with `# `, and ends at the last `# ///` line among them.
+pub fn parse(source: &str) -> Result<Option<String>> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut found = None;
+    let mut index = 0;
+    while index < lines.len() {
+        let Some(kind) = lines[index].strip_prefix("# /// ") else {
+            index += 1;
+            continue;
+        };
+        let start = index + 1;
+        let end = start
+            + lines[start..]
+                .iter()
+                .take_while(|line| **line == "#" || line.starts_with("# "))
+                .count();
+        let Some(close) = (start..end).rev().find(|&line| lines[line] == "# ///") else {
+            return Err(eyre!(
+                "Unclosed '# /// {kind}' block on line {} (expected a '# ///' line)",
+                index + 1
+            ));
+        };
+        if kind == "script" {
+            if found.is_some() {
+                return Err(eyre!(
+                    "More than one '# /// script' block (the second is on line {})",
+                    index + 1
+                ));
+            }
+            let content = lines[start..close]
+                .iter()
+                .map(|line| line.strip_prefix("# ").unwrap_or(&line[1..]))
+                .collect::<Vec<_>>();
+            found = Some(content.join("\n") + "\n");
+        }
+        index = close + 1;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn should_extract_script_block() {
+        let source = "#!/usr/bin/env python\n\
+                      # /// script\n\
+                      # requires-python = \">=3.11\"\n\
+                      # dependencies = [\n\
+                      #   \"requests<3\",\n\
+                      # ]\n\
+                      #\n\
+                      # [tool.rpy]\n\
+                      # env = { A = '1' }\n\
+                      # ///\n\
+                      \n\
+                      import requests\n";
+        assert_eq!(
+            parse(source).unwrap().unwrap(),
+            "requires-python = \">=3.11\"\n\
+             dependencies = [\n  \"requests<3\",\n]\n\n[tool.rpy]\nenv = { A = '1' }\n"
+        );
+    }
+
+    #[test]
+    fn should_ignore_other_blocks_and_plain_comments() {
+        assert_eq!(parse("# just a comment\nprint(1)\n").unwrap(), None);
+        assert_eq!(parse("# /// other\n# x = 1\n# ///\n").unwrap(), None);
+    }
+
+    #[test]
+    fn should_reject_bad_blocks() {
+        assert_eq!(
+            parse("# /// script\n# x = 1\nprint(1)\n")
+                .unwrap_err()
+                .to_string(),
+            "Unclosed '# /// script' block on line 1 (expected a '# ///' line)"
+        );
+        assert_eq!(
+            parse("# /// script\n# ///\nx = 1\n# /// script\n# ///\n")
+                .unwrap_err()
+                .to_string(),
+            "More than one '# /// script' block (the second is on line 4)"
+        );
+    }
+}
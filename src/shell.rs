@@ -0,0 +1,46 @@
+#![deny(warnings)]
+
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+use eyre::Result;
+
+use crate::plan::Launch;
+
+/// The variable set in the shell to the project root, for use in prompts.
+pub const MARKER: &str = "RPY_SHELL";
+
+/// The command that starts the user's shell (`$SHELL`, or `/bin/sh`) with the environment python
+/// would be run with. If the interpreter is in a virtual environment, that is activated too, so
+/// that `python` and the tools installed alongside it are the project's.
+pub fn command(project_root: &Path, launch: &mut Launch, verbose: bool) -> Result<Command> {
+    let shell = env::var_os("SHELL")
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| OsString::from("/bin/sh"));
+    if verbose {
+        println!("shell: {}", shell.to_string_lossy());
+    }
+    let environment = &mut launch.environment;
+    if let Some(bin) = launch.interpreter.path.parent()
+        && let Some(venv) = bin.parent()
+        && venv.join("pyvenv.cfg").is_file()
+    {
+        environment.prepend_paths("PATH", vec![bin.to_path_buf()])?;
+        environment.set("VIRTUAL_ENV", venv);
+    }
+    environment.set(MARKER, project_root);
+    let mut cmd = Command::new(shell);
+    environment.apply(&mut cmd);
+    // rpy only keeps its own variables from python; whatever is run from the shell may be rpy.
+    for (name, value) in environment.changes() {
+        if value.is_none()
+            && name.starts_with("RPY_")
+            && let Some(inherited) = env::var_os(name)
+        {
+            cmd.env(name, inherited);
+        }
+    }
+    Ok(cmd)
+}
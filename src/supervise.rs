@@ -0,0 +1,214 @@
+#![deny(warnings)]
+
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use eyre::{Result, WrapErr};
+
+/// Signals rpy passes on to the supervised interpreter.
+const FORWARDED: [i32; 4] = [libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2];
+
+/// Signals a terminal sends to its whole foreground process group. When the interpreter is in
+/// that group it has them already, so rpy only passes them on when it isn't (there is no
+/// terminal, or rpy runs in the background), e.g. when sent by `kill -INT`.
+const FROM_TERMINAL: [i32; 2] = [libc::SIGINT, libc::SIGQUIT];
+
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// A descriptor for rpy's controlling terminal, or -1 if it has none.
+static TTY: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn forward(signal: libc::c_int) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    if pid > 0 {
+        // SAFETY: kill is async-signal-safe.
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+extern "C" fn forward_from_elsewhere(signal: libc::c_int) {
+    let tty = TTY.load(Ordering::SeqCst);
+    // SAFETY: tcgetpgrp and getpgrp are async-signal-safe. The interpreter shares rpy's process
+    // group.
+    let in_foreground = tty >= 0 && unsafe { libc::tcgetpgrp(tty) == libc::getpgrp() };
+    if !in_foreground {
+        forward(signal);
+    }
+}
+
+fn set_handler(signal: i32, handler: libc::sighandler_t) {
+    // SAFETY: the handlers installed are either SIG_DFL, SIG_IGN, `forward` or
+    // `forward_from_elsewhere`, which only do async-signal-safe things.
+    unsafe {
+        libc::signal(signal, handler);
+    }
+}
+
+/// While alive, rpy ignores the signals in `FROM_TERMINAL`, so that a Ctrl-C meant for a pre_run
+/// or post_run step (which the terminal sends to rpy too) leaves rpy to report how it ended.
+/// Commands it is given to get rpy's previous handling of them back.
+pub struct IgnoringInterrupts {
+    previous: [libc::sighandler_t; 2],
+}
+
+impl IgnoringInterrupts {
+    pub fn new(cmd: &mut Command) -> Self {
+        let previous = FROM_TERMINAL.map(|signal| {
+            // SAFETY: ignoring a signal; the previous disposition is restored on drop.
+            unsafe { libc::signal(signal, libc::SIG_IGN) }
+        });
+        // SAFETY: signal is async-signal-safe, and the previous dispositions are SIG_DFL or
+        // SIG_IGN as rpy installs no handlers of its own outside `spawn_and_wait`.
+        unsafe {
+            cmd.pre_exec(move || {
+                for (signal, handler) in FROM_TERMINAL.into_iter().zip(previous) {
+                    libc::signal(signal, handler);
+                }
+                Ok(())
+            });
+        }
+        IgnoringInterrupts { previous }
+    }
+}
+
+impl Drop for IgnoringInterrupts {
+    fn drop(&mut self) {
+        for (signal, handler) in FROM_TERMINAL.into_iter().zip(self.previous) {
+            set_handler(signal, handler);
+        }
+    }
+}
+
+/// Blocks the signals rpy handles while supervising, returning the previous signal mask.
+fn block_handled() -> libc::sigset_t {
+    // SAFETY: sigset_t is plain data that sigemptyset initialises.
+    unsafe {
+        let mut set = std::mem::zeroed();
+        let mut previous = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in FROM_TERMINAL.into_iter().chain(FORWARDED) {
+            libc::sigaddset(&mut set, signal);
+        }
+        libc::sigprocmask(libc::SIG_BLOCK, &set, &mut previous);
+        previous
+    }
+}
+
+fn set_mask(mask: &libc::sigset_t) {
+    // SAFETY: sigprocmask is given a mask sigprocmask returned.
+    unsafe {
+        libc::sigprocmask(libc::SIG_SETMASK, mask, std::ptr::null_mut());
+    }
+}
+
+/// Runs `cmd` as a child of rpy rather than replacing rpy with it, so that rpy can do things
+/// after it finishes. The child shares rpy's stdin, stdout, stderr and so its TTY. While it runs
+/// the signals in `FORWARDED` are passed on to it, as are those in `FROM_TERMINAL` unless the
+/// terminal has delivered them to it already, so it sees what it would have if run directly.
+///
+/// Those signals are blocked from before the child starts until rpy is ready to pass them on,
+/// so that one arriving in between is passed on late rather than killing rpy and orphaning the
+/// child. The child starts with rpy's original mask.
+pub fn spawn_and_wait(cmd: &mut Command) -> Result<ExitStatus> {
+    let mask = block_handled();
+    // SAFETY: sigprocmask is async-signal-safe.
+    unsafe {
+        cmd.pre_exec(move || {
+            set_mask(&mask);
+            Ok(())
+        });
+    }
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            set_mask(&mask);
+            return Err(err).wrap_err("Unable to start the python interpreter");
+        }
+    };
+    CHILD.store(child.id() as i32, Ordering::SeqCst);
+    // SAFETY: opening a path with a NUL-terminated literal.
+    let tty = unsafe {
+        libc::open(
+            c"/dev/tty".as_ptr(),
+            libc::O_RDONLY | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    TTY.store(tty, Ordering::SeqCst);
+    for signal in FROM_TERMINAL {
+        set_handler(
+            signal,
+            forward_from_elsewhere as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    for signal in FORWARDED {
+        set_handler(
+            signal,
+            forward as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    set_mask(&mask);
+    let status = child.wait();
+    for signal in FROM_TERMINAL.into_iter().chain(FORWARDED) {
+        set_handler(signal, libc::SIG_DFL);
+    }
+    CHILD.store(0, Ordering::SeqCst);
+    TTY.store(-1, Ordering::SeqCst);
+    if tty >= 0 {
+        // SAFETY: closing the descriptor opened above, which nothing else uses now.
+        unsafe {
+            libc::close(tty);
+        }
+    }
+    status.wrap_err("Unable to wait for the python interpreter")
+}
+
+/// Ends rpy the way the supervised interpreter ended: exiting with its exit code, or dying from
+/// the same signal, so whoever started rpy sees exactly what it would have without supervision.
+pub fn exit_like(status: ExitStatus) -> ! {
+    if let Some(signal) = status.signal() {
+        die_from(signal);
+    }
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+/// Ends rpy by killing it with `signal`, as a process that had it and didn't handle it would end.
+pub fn die_from(signal: i32) -> ! {
+    set_handler(signal, libc::SIG_DFL);
+    // SAFETY: raising a signal with its default disposition on ourselves.
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+    // Only reached for signals whose default action is not to terminate.
+    std::process::exit(128 + signal);
+}
+
+/// The conventional name of `signal`, e.g. `SIGTERM`.
+pub fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => "unknown",
+    }
+}
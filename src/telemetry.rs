@@ -0,0 +1,51 @@
+#![deny(warnings)]
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+
+/// One line of the telemetry log, describing a single supervised run.
+#[derive(Serialize, Debug)]
+pub struct Record<'a> {
+    /// When the run finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub project_root: &'a Path,
+    pub invocation: &'static str,
+    /// The script or module run, if any.
+    pub target: Option<&'a str>,
+    pub interpreter: &'a Path,
+    /// How long pre_run took, or `null` if there was none.
+    pub pre_run_ms: Option<u64>,
+    pub script_ms: u64,
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<&'static str>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Appends `record` to the log at `path` as a single JSON line, creating the file and its
+/// directory if need be.
+/// The line is written with one `write` call so concurrent runs don't interleave.
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .wrap_err(format!("Unable to create directory {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err(format!("Unable to open telemetry log {}", path.display()))?;
+    file.write_all(line.as_bytes())
+        .wrap_err(format!("Unable to write telemetry log {}", path.display()))
+}
@@ -0,0 +1,285 @@
+#![deny(warnings)]
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use eyre::{Result, eyre};
+
+/// The release part of a python version, e.g. `3.11.4`. Anything after it (`rc1`, `+local`...)
+/// is ignored.
+#[derive(Debug, Clone)]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    pub fn parse(version: &str) -> Result<Version> {
+        let release = version
+            .trim()
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('.');
+        let parts = release
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| eyre!("Invalid python version '{version}'"))?;
+        Ok(Version(parts))
+    }
+
+    pub fn major_minor(&self) -> (u64, u64) {
+        (self.part(0), self.part(1))
+    }
+
+    fn part(&self, index: usize) -> u64 {
+        self.0.get(index).copied().unwrap_or(0)
+    }
+
+    fn starts_with(&self, prefix: &[u64]) -> bool {
+        prefix
+            .iter()
+            .enumerate()
+            .all(|(index, part)| self.part(index) == *part)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|index| self.part(index).cmp(&other.part(index)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self.0.iter().map(u64::to_string).collect::<Vec<_>>();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Compatible,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Less,
+    Greater,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Specifier {
+    operator: Operator,
+    version: Version,
+    wildcard: bool,
+}
+
+/// A PEP 440 version specifier set such as `>=3.10,<3.13`, as used by `requires-python`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Specifiers {
+    text: String,
+    specifiers: Vec<Specifier>,
+}
+
+impl Specifiers {
+    pub fn parse(text: &str) -> Result<Specifiers> {
+        let specifiers = text
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+            .map(|spec| {
+                Self::parse_one(spec)
+                    .ok_or_else(|| eyre!("Invalid version specifier '{spec}' in '{text}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Specifiers {
+            text: text.trim().to_string(),
+            specifiers,
+        })
+    }
+
+    fn parse_one(spec: &str) -> Option<Specifier> {
+        let (operator, rest) = [
+            ("~=", Operator::Compatible),
+            ("===", Operator::Equal),
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessEqual),
+            (">=", Operator::GreaterEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+        ]
+        .into_iter()
+        .find_map(|(prefix, operator)| spec.strip_prefix(prefix).map(|rest| (operator, rest)))?;
+        let rest = rest.trim();
+        let (rest, wildcard) = match rest.strip_suffix(".*") {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        };
+        if wildcard && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+            return None;
+        }
+        if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+        let version = Version::parse(rest).ok()?;
+        if operator == Operator::Compatible && version.0.len() < 2 {
+            return None;
+        }
+        Some(Specifier {
+            operator,
+            version,
+            wildcard,
+        })
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        self.specifiers.iter().all(|spec| spec.contains(version))
+    }
+
+    /// Whether any `major.minor.*` release could satisfy these specifiers. Used when all that's
+    /// known about an interpreter is its name, e.g. `python3.11`.
+    pub fn allows_series(&self, major: u64, minor: u64) -> bool {
+        self.specifiers
+            .iter()
+            .all(|spec| spec.allows_series(major, minor))
+    }
+}
+
+impl fmt::Display for Specifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Specifier {
+    fn contains(&self, version: &Version) -> bool {
+        let spec = &self.version;
+        match self.operator {
+            Operator::Equal if self.wildcard => version.starts_with(&spec.0),
+            Operator::NotEqual if self.wildcard => !version.starts_with(&spec.0),
+            Operator::Equal => version == spec,
+            Operator::NotEqual => version != spec,
+            Operator::LessEqual => version <= spec,
+            Operator::GreaterEqual => version >= spec,
+            Operator::Less => version < spec,
+            Operator::Greater => version > spec,
+            Operator::Compatible => {
+                version >= spec && version.starts_with(&spec.0[..spec.0.len() - 1])
+            }
+        }
+    }
+
+    fn allows_series(&self, major: u64, minor: u64) -> bool {
+        let spec = &self.version;
+        let series = (major, minor);
+        let first = Version(vec![major, minor]);
+        match self.operator {
+            Operator::Equal if self.wildcard && spec.0.len() == 1 => spec.part(0) == major,
+            Operator::Equal => spec.major_minor() == series,
+            Operator::NotEqual => {
+                !(self.wildcard && spec.0.len() <= 2 && first.starts_with(&spec.0))
+            }
+            Operator::LessEqual => &first <= spec,
+            Operator::Less => &first < spec,
+            Operator::GreaterEqual | Operator::Greater => spec.major_minor() <= series,
+            Operator::Compatible => {
+                spec.major_minor() <= series
+                    && first.starts_with(&spec.0[..(spec.0.len() - 1).min(2)])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Specifiers, Version};
+
+    fn contains(specifiers: &str, version: &str) -> bool {
+        Specifiers::parse(specifiers)
+            .unwrap()
+            .contains(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn should_parse_versions() {
+        assert_eq!(Version::parse("3.11.4").unwrap(), Version(vec![3, 11, 4]));
+        assert_eq!(
+            Version::parse("3.12.0rc1").unwrap(),
+            Version(vec![3, 12, 0])
+        );
+        assert_eq!(Version::parse("3.13").unwrap(), Version(vec![3, 13]));
+        assert!(Version::parse("python").is_err());
+        assert_eq!(
+            Version::parse("3.10").unwrap(),
+            Version::parse("3.10.0").unwrap()
+        );
+        assert!(Version::parse("3.9.18").unwrap() < Version::parse("3.10").unwrap());
+    }
+
+    #[test]
+    fn should_check_comparisons() {
+        assert!(contains(">=3.10", "3.10.0"));
+        assert!(contains(">=3.10", "3.12.1"));
+        assert!(!contains(">=3.10", "3.9.18"));
+        assert!(contains(">=3.10, <3.12", "3.11.7"));
+        assert!(!contains(">=3.10, <3.12", "3.12.0"));
+        assert!(contains(">3.10", "3.10.1"));
+        assert!(!contains("<=3.10", "3.10.1"));
+        assert!(contains("", "2.7"));
+    }
+
+    #[test]
+    fn should_check_equality_and_wildcards() {
+        assert!(contains("==3.11.4", "3.11.4"));
+        assert!(!contains("==3.11.4", "3.11.5"));
+        assert!(contains("==3.11.*", "3.11.5"));
+        assert!(!contains("==3.11.*", "3.12.0"));
+        assert!(contains("!=3.11.*", "3.12.0"));
+        assert!(!contains("!=3.11.*", "3.11.2"));
+        assert!(contains("~=3.10", "3.12.0"));
+        assert!(!contains("~=3.10", "4.0"));
+        assert!(contains("~=3.10.2", "3.10.9"));
+        assert!(!contains("~=3.10.2", "3.11.0"));
+    }
+
+    #[test]
+    fn should_reject_invalid_specifiers() {
+        assert_eq!(
+            Specifiers::parse(">=3.10, =>4").unwrap_err().to_string(),
+            "Invalid version specifier '=>4' in '>=3.10, =>4'"
+        );
+        assert!(Specifiers::parse(">=3.*").is_err());
+        assert!(Specifiers::parse("~=3").is_err());
+    }
+
+    #[test]
+    fn should_check_whole_series() {
+        let spec = Specifiers::parse(">=3.10.2, <3.13").unwrap();
+        assert!(!spec.allows_series(3, 9));
+        assert!(spec.allows_series(3, 10));
+        assert!(spec.allows_series(3, 12));
+        assert!(!spec.allows_series(3, 13));
+        assert!(Specifiers::parse("==3.11.4").unwrap().allows_series(3, 11));
+        assert!(!Specifiers::parse("!=3.11.*").unwrap().allows_series(3, 11));
+        assert!(Specifiers::parse("~=3.10").unwrap().allows_series(3, 12));
+        assert!(!Specifiers::parse("~=3.10.1").unwrap().allows_series(3, 11));
+    }
+}
@@ -0,0 +1,5 @@
+echo badger
+echo $PYTHONPATH
+echo $PYTHONSAFEPATH
+echo $RPY_TEST_A
+echo $RPY_TEST_B
@@ -0,0 +1,7 @@
+echo badger
+echo $OMP_NUM_THREADS
+echo $RPY_TEST_DATA
+echo $RPY_TEST_SOURCE
+echo $RPY_TEST_FROM_ENV
+echo $RPY_TEST_PATH
+echo ${RPY_TEST_REMOVED-removed}
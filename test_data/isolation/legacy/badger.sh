@@ -0,0 +1,3 @@
+echo badger
+echo ${PYTHONNOUSERSITE-unset}
+echo ${PYTHONSAFEPATH-unset}
@@ -0,0 +1 @@
+echo "$FROM_PYPROJECT $FROM_RPY $SHARED ${PROFILE:-} ${PROFILE_EXTRA:-}"
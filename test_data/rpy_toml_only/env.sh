@@ -0,0 +1 @@
+echo "$FROM_RPY"
@@ -0,0 +1,10 @@
+# /// script
+# dependencies = [
+#   "requests<3",
+#   "rich",
+# ]
+#
+# [tool.rpy]
+# script_env = true
+# ///
+echo "in script env"
@@ -0,0 +1,7 @@
+# /// script
+# requires-python = ">=3.13"
+#
+# [tool.rpy]
+# interpreter = 'bin/python'
+# ///
+echo "should not run"
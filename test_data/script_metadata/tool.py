@@ -0,0 +1,9 @@
+# /// script
+# requires-python = ">=3.11"
+# dependencies = []
+#
+# [tool.rpy]
+# interpreter = 'bin/python'
+# env = { GREETING = 'hello from ${PROJECT_ROOT}' }
+# ///
+echo "$GREETING"
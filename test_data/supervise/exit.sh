@@ -0,0 +1,2 @@
+echo "script $*"
+exit "$1"
@@ -0,0 +1,6 @@
+trap 'echo "got TERM"; exit 7' TERM
+trap 'echo "got INT"; exit 8' INT
+trap 'echo "got QUIT"; exit 9' QUIT
+echo ready
+sleep 5 >/dev/null 2>&1 &
+wait
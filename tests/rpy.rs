@@ -1,8 +1,20 @@
-use std::{env, path::Path, process::Command};
+use std::{
+    env,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
 
 const SRC_ROOT: &str = env!("CARGO_MANIFEST_DIR");
 const RPY_EXE: &str = env!("CARGO_BIN_EXE_rpy");
 
+/// An empty cache directory for a test to use as `XDG_CACHE_HOME`.
+fn fresh_cache_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::remove_dir_all(&dir).ok();
+    dir
+}
+
 #[test]
 fn should_fail_with_no_pyproject_toml() {
     let output = Command::new(RPY_EXE).current_dir("/").output().unwrap();
@@ -10,7 +22,9 @@ fn should_fail_with_no_pyproject_toml() {
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     assert_eq!(stdout, "");
     assert!(
-        stderr.starts_with("[rpy] Error: Unable to find pyproject.toml from /\n"),
+        stderr.starts_with(
+            "[rpy] Error: Unable to find pyproject.toml, rpy.toml or .rpy.toml from /\n"
+        ),
         "{stderr}"
     );
     assert_eq!(output.status.code().unwrap(), 1);
@@ -73,111 +87,1913 @@ fn should_work_with_pre_run_pyproject_toml() {
 }
 
 #[test]
-fn should_work_with_source_root_pyproject_toml() {
+fn should_only_pre_run_when_inputs_change() {
+    let cache_dir = fresh_cache_dir("pre_run_inputs_cache");
+    let project = Path::new(SRC_ROOT).join("test_data/pre_run_inputs");
+    let generated = project.join("generated");
+    std::fs::remove_dir_all(&generated).ok();
+    let run = |force: bool| {
+        let mut cmd = Command::new(RPY_EXE);
+        cmd.current_dir(&project)
+            .arg("badger.sh")
+            .env("XDG_CACHE_HOME", &cache_dir)
+            .env_remove("RPY_FORCE_PRE_RUN");
+        if force {
+            cmd.env("RPY_FORCE_PRE_RUN", "1");
+        }
+        let output = cmd.output().unwrap();
+        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "badger\n");
+        assert_eq!(output.status.code().unwrap(), 0);
+        std::str::from_utf8(&output.stderr).unwrap().to_string()
+    };
+    assert_eq!(run(false), "prerun\n");
+    assert_eq!(run(false), "");
+    assert_eq!(run(true), "prerun\n");
+    std::fs::create_dir_all(&generated).unwrap();
+    std::fs::write(generated.join("requirements.txt"), "badger==1.0\n").unwrap();
+    assert_eq!(run(false), "prerun\n");
+    assert_eq!(run(false), "");
+    std::fs::write(generated.join("requirements.txt"), "badger==2.0\n").unwrap();
+    assert_eq!(run(false), "prerun\n");
+}
+
+#[test]
+fn should_run_pre_run_once_for_concurrent_invocations() {
+    let cache_dir = fresh_cache_dir("pre_run_lock_cache");
+    let log = cache_dir.with_extension("log");
+    std::fs::remove_file(&log).ok();
+    let children = (0..5)
+        .map(|_| {
+            Command::new(RPY_EXE)
+                .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_lock"))
+                .arg("badger.sh")
+                .env("XDG_CACHE_HOME", &cache_dir)
+                .env("RPY_TEST_PRE_RUN_LOG", &log)
+                .env_remove("RPY_PROFILE")
+                .env_remove("RPY_FORCE_PRE_RUN")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "badger\n");
+        assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+        assert_eq!(output.status.code().unwrap(), 0);
+    }
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "ran\n");
+}
+
+#[test]
+fn should_time_out_waiting_for_pre_run_lock() {
+    let project = Path::new(SRC_ROOT).join("test_data/pre_run_lock");
+    let lock = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(project.join(".rpy-pre-run.lock"))
+        .unwrap();
+    lock.lock().unwrap();
     let output = Command::new(RPY_EXE)
-        .current_dir(Path::new(SRC_ROOT).join("test_data/source_root"))
+        .current_dir(&project)
         .arg("badger.sh")
+        .env(
+            "XDG_CACHE_HOME",
+            fresh_cache_dir("pre_run_lock_timeout_cache"),
+        )
+        .env("RPY_PROFILE", "impatient")
         .output()
         .unwrap();
+    lock.unlock().unwrap();
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
-    assert_eq!(stderr, "");
-    assert_eq!(
-        stdout,
-        "badger\n".to_string() + SRC_ROOT + "/test_data/source_root/src\n"
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.contains(&format!(
+            "Timed out after 0s waiting for the pre_run lock {}/.rpy-pre-run.lock held by ",
+            project.display()
+        )),
+        "{stderr}"
     );
-    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.status.code().unwrap(), 1);
 }
 
 #[test]
-fn should_work_with_bin_path_pyproject_toml() {
+fn should_run_pre_run_steps_in_order() {
     let output = Command::new(RPY_EXE)
-        .current_dir(Path::new(SRC_ROOT).join("test_data/bin_path"))
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
         .arg("badger.sh")
+        .env("RPY_VERBOSE", "1")
+        .env_remove("RPY_PROFILE")
         .output()
         .unwrap();
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
-    assert_eq!(
-        stdout,
-        "badger\n".to_string()
-            + SRC_ROOT
-            + "/test_data/bin_path/bin:"
-            + &env::var("PATH").unwrap()
-            + "\n"
+    let root = SRC_ROOT.to_string() + "/test_data/pre_run_steps";
+    assert_eq!(stderr, format!("first {root}/sub {root}\nsecond\n"));
+    assert!(
+        stdout.contains(
+            "\nrunning pre_run step 'first': echo \"first $PWD $STEP_VAR\"\n\
+             running pre_run step 'pre_run[1]': echo second\n"
+        ),
+        "{stdout}"
     );
-    assert_eq!(stderr, "");
+    assert!(stdout.ends_with("\nbadger\n"), "{stdout}");
     assert_eq!(output.status.code().unwrap(), 0);
 }
 
 #[test]
-fn should_work_with_rel_interp_pyproject_toml() {
+fn should_clear_pre_run_from_a_profile() {
     let output = Command::new(RPY_EXE)
-        .current_dir(Path::new(SRC_ROOT).join("test_data/rel_interp"))
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
         .arg("badger.sh")
-        .arg("foo")
+        .env("RPY_PROFILE", "cleared")
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "badger\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_name_failing_pre_run_step() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "failing")
         .output()
         .unwrap();
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
-    assert_eq!(stderr, "");
-    assert_eq!(stdout, "interp\nbadger.sh foo\n");
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.starts_with(
+            "first\n[rpy] Error: Unable to run pre_run step\n\n\
+             Caused by:\n    Pre-run step 'broken' failed with exit code 3\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+fn run_pre_run_steps_profile(profile: &str) -> (String, ExitStatus) {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", profile)
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    let stderr = std::str::from_utf8(&output.stderr).unwrap().to_string();
+    (stderr, output.status)
+}
+
+#[test]
+fn should_report_pre_run_killed_by_signal() {
+    let (stderr, status) = run_pre_run_steps_profile("killed");
+    assert!(
+        stderr.contains("Pre-run step 'kill -TERM $$' was killed by signal SIGTERM (15)\n"),
+        "{stderr}"
+    );
+    assert_eq!(status.signal(), Some(libc::SIGTERM));
+}
+
+#[test]
+fn should_die_from_interrupt_when_pre_run_is_interrupted() {
+    let (stderr, status) = run_pre_run_steps_profile("interrupted");
+    assert!(
+        stderr.contains("was killed by signal SIGINT (2)\n"),
+        "{stderr}"
+    );
+    assert_eq!(status.signal(), Some(libc::SIGINT));
+}
+
+#[test]
+fn should_report_pre_run_interrupted_with_its_process_group() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::os::unix::process::CommandExt;
+    let mut command = Command::new(RPY_EXE);
+    command
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "slow")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    // SAFETY: setsid is async-signal-safe.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert_eq!(line, "started\n");
+    // As a terminal's Ctrl-C does, to rpy and the step alike.
+    let kill = Command::new("kill")
+        .args(["-INT", "--", &format!("-{}", child.id())])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+    let mut rest = String::new();
+    stderr.read_to_string(&mut rest).unwrap();
+    assert!(
+        rest.contains(
+            "Pre-run step 'echo started; sleep 5' was killed by signal SIGINT (2)\n    \
+             Last 1 lines of its output:\n      started\n"
+        ),
+        "{rest}"
+    );
+    assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGINT));
+}
+
+#[test]
+fn should_include_captured_pre_run_output_in_error() {
+    let (stderr, status) = run_pre_run_steps_profile("captured");
+    assert!(
+        stderr.starts_with(
+            "one\ntwo\nthree\n[rpy] Error: Unable to run pre_run step\n\n\
+             Caused by:\n    Pre-run step 'echo one; echo two >&2; echo three; exit 4' failed \
+             with exit code 4\n    Last 2 lines of its output:\n      two\n      three\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn should_run_pre_run_with_configured_shell_and_argv() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_shell"))
+        .arg("badger.sh")
+        .env("RPY_VERBOSE", "1")
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "shell sh\nno $expansion\n");
+    assert!(
+        stdout.contains("\nrunning pre_run step 'argv': printf %s\\n no $expansion\n"),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("\nbadger\n"), "{stdout}");
     assert_eq!(output.status.code().unwrap(), 0);
 }
 
 #[test]
-fn should_work_with_rel_rpy_interpreter_environment_override() {
+fn should_report_missing_pre_run_shell() {
     let output = Command::new(RPY_EXE)
-        .arg(Path::new(SRC_ROOT).join("test_data/rel_interp/badger.sh"))
-        .arg("foo")
-        .env("RPY_INTERPRETER", "bin/interp2")
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_shell"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "missing_shell")
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: Unable to run pre_run step\n\n\
+             Caused by:\n    The pre_run shell 'rpy-no-such-shell' was not found; set \
+             pre_run_shell to use another, e.g. pre_run_shell = [\"sh\", \"-c\"]\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_report_missing_pre_run_command() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_shell"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "missing_command")
         .output()
         .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: Unable to run pre_run step\n\n\
+             Caused by:\n    The command 'rpy-no-such-command' of pre_run step \
+             'rpy-no-such-command --flag' was not found\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+fn supervise_command(args: &[&str]) -> Command {
+    let mut command = Command::new(RPY_EXE);
+    command
+        .current_dir(Path::new(SRC_ROOT).join("test_data/supervise"))
+        .args(args)
+        .env_remove("RPY_PROFILE");
+    command
+}
+
+#[test]
+fn should_run_post_run_with_exit_code() {
+    let output = supervise_command(&["exit.sh", "3"]).output().unwrap();
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "script 3\n");
+    assert_eq!(
+        stderr,
+        format!("post_run exit=3 signal=none {SRC_ROOT}/test_data/supervise\n")
+    );
+    assert_eq!(output.status.code().unwrap(), 3);
+}
+
+#[test]
+fn should_die_from_the_same_signal_when_supervising() {
+    let output = supervise_command(&["killed.sh"]).output().unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        format!("post_run exit=143 signal=SIGTERM {SRC_ROOT}/test_data/supervise\n")
+    );
+    assert_eq!(output.status.code(), None);
+    assert_eq!(output.status.signal(), Some(15));
+}
+
+/// Runs trap.sh supervised, in a session of its own (so without a controlling terminal), and
+/// sends `signal` to rpy once it's ready, returning what it printed and its exit code.
+fn send_supervised_signal(signal: &str) -> (String, i32) {
+    use std::io::{BufRead, BufReader, Read};
+    use std::os::unix::process::CommandExt;
+    let mut command = supervise_command(&["trap.sh"]);
+    command
+        .env("RPY_PROFILE", "supervised_only")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // SAFETY: setsid is async-signal-safe.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\n");
+    let kill = Command::new("kill")
+        .args([&format!("-{signal}"), &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
     assert_eq!(stderr, "");
+    (rest, child.wait().unwrap().code().unwrap())
+}
+
+#[test]
+fn should_forward_signals_when_supervising() {
+    assert_eq!(send_supervised_signal("TERM"), ("got TERM\n".into(), 7));
+}
+
+#[test]
+fn should_forward_interrupts_without_a_terminal_when_supervising() {
+    assert_eq!(send_supervised_signal("INT"), ("got INT\n".into(), 8));
+    assert_eq!(send_supervised_signal("QUIT"), ("got QUIT\n".into(), 9));
+}
+
+#[test]
+fn should_keep_exit_code_when_post_run_fails() {
+    let output = supervise_command(&["exit.sh", "0"])
+        .env("RPY_PROFILE", "failing_hook")
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: Unable to run post_run step\n\n\
+             Caused by:\n    Post-run step 'exit 9' failed with exit code 9\n"
+        ),
+        "{stderr}"
+    );
     assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_append_telemetry_for_each_run() {
+    let log = fresh_cache_dir("telemetry").join("runs.jsonl");
+    for code in ["0", "4"] {
+        let output = supervise_command(&["exit.sh", code])
+            .env("RPY_PROFILE", "telemetry")
+            .env("RPY_TEST_TELEMETRY", &log)
+            .output()
+            .unwrap();
+        assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+        assert_eq!(output.status.code().unwrap().to_string(), code);
+    }
+    let contents = std::fs::read_to_string(&log).unwrap();
+    let records = contents
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(records.len(), 2);
+    let root = SRC_ROOT.to_string() + "/test_data/supervise";
+    for (record, code) in records.iter().zip([0, 4]) {
+        assert_eq!(record["project_root"], root.as_str());
+        assert_eq!(record["invocation"], "File");
+        assert_eq!(record["target"], "exit.sh");
+        assert_eq!(record["interpreter"], "bash");
+        assert!(record["pre_run_ms"].is_u64(), "{record}");
+        assert!(record["script_ms"].is_u64(), "{record}");
+        assert!(record["timestamp"].is_u64(), "{record}");
+        assert_eq!(record["exit_code"], code);
+        assert!(record["exit_signal"].is_null(), "{record}");
+    }
+}
+
+fn run_env_manager(profile: Option<&str>, cache: &Path) -> (String, String, i32) {
+    let root = Path::new(SRC_ROOT).join("test_data/env_manager");
+    let path = format!(
+        "{}:{}",
+        root.join("bin").display(),
+        env::var("PATH").unwrap()
+    );
+    let mut command = Command::new(RPY_EXE);
+    command
+        .current_dir(&root)
+        .arg("badger.sh")
+        .env("PATH", path)
+        .env("XDG_CACHE_HOME", cache)
+        .env("RPY_TEST_ENV_LOG", cache.join("env.log"))
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER");
+    if let Some(profile) = profile {
+        command.env("RPY_PROFILE", profile);
+    }
+    let output = command.output().unwrap();
+    let log = std::fs::read_to_string(cache.join("env.log")).unwrap_or_default();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap().to_string();
+    (stdout, log, output.status.code().unwrap())
+}
+
+#[test]
+fn should_provision_env_with_uv_once() {
+    let root = SRC_ROOT.to_string() + "/test_data/env_manager";
+    std::fs::remove_dir_all(Path::new(&root).join(".venv")).ok();
+    let cache = fresh_cache_dir("env_manager_uv");
+    std::fs::create_dir_all(&cache).unwrap();
+    let (stdout, log, code) = run_env_manager(None, &cache);
+    assert_eq!(stdout, "badger\n");
     assert_eq!(
-        stdout,
-        "interp2\n".to_string()
-            + &Path::new(SRC_ROOT)
-                .join("test_data/rel_interp/badger.sh")
-                .display()
-                .to_string()
-            + " foo\n"
+        log,
+        format!(
+            "uv venv --allow-existing --quiet {root}/.venv\n\
+             uv pip sync --quiet --python {root}/.venv/bin/python {root}/requirements.lock\n"
+        )
     );
+    assert_eq!(code, 0);
+    let (stdout, second_log, code) = run_env_manager(None, &cache);
+    assert_eq!(stdout, "badger\n");
+    assert_eq!(second_log, log);
+    assert_eq!(code, 0);
 }
 
 #[test]
-fn should_work_with_abs_rpy_interpreter_environment_override() {
-    let output = Command::new(RPY_EXE)
-        .arg(Path::new(SRC_ROOT).join("test_data/rel_interp/badger.sh"))
-        .arg("foo")
-        .env("RPY_INTERPRETER", "interp2")
-        .env(
-            "PATH",
-            Path::new(SRC_ROOT)
-                .join("test_data/rel_interp/bin")
-                .display()
-                .to_string()
-                + ":"
-                + &env::var("PATH").unwrap(),
+fn should_provision_env_with_pip_from_wheelhouse() {
+    let root = SRC_ROOT.to_string() + "/test_data/env_manager";
+    std::fs::remove_dir_all(Path::new(&root).join("pip-env")).ok();
+    let cache = fresh_cache_dir("env_manager_pip");
+    std::fs::create_dir_all(&cache).unwrap();
+    let (stdout, log, code) = run_env_manager(Some("pip"), &cache);
+    assert_eq!(stdout, "badger\n");
+    assert_eq!(
+        log,
+        format!(
+            "python3 -m venv {root}/pip-env\n\
+             python -m pip install --quiet -r {root}/requirements.lock --no-index --find-links \
+             {root}/wheels\n"
         )
+    );
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn should_reject_interpreter_with_env_manager() {
+    let cache = fresh_cache_dir("env_manager_conflict");
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/env_manager"))
+        .arg("badger.sh")
+        .env("XDG_CACHE_HOME", &cache)
+        .env("RPY_PROFILE", "conflict")
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr
+            .starts_with("[rpy] Error: Set either interpreter or [tool.rpy.env_manager], not both"),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+/// Copies the standalone scripts to somewhere with no pyproject.toml above them.
+fn standalone_scripts(name: &str) -> PathBuf {
+    let dir = fresh_cache_dir(name);
+    let status = Command::new("cp")
+        .arg("-r")
+        .arg(Path::new(SRC_ROOT).join("test_data/script_metadata"))
+        .arg(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    dir
+}
+
+#[test]
+fn should_configure_standalone_script_from_inline_metadata() {
+    let dir = standalone_scripts("script_metadata");
+    let output = Command::new(RPY_EXE)
+        .current_dir(&dir)
+        .arg("tool.py")
+        .env("RPY_VERBOSE", "1")
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER")
         .output()
         .unwrap();
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let dir = dir.display();
+    assert!(
+        stdout.starts_with(&format!(
+            "project root: {dir}\nscript metadata: {dir}/tool.py\n"
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("python version: 3.12.1 (requires-python >=3.11)\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(&format!("\nhello from {dir}\n")),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_check_inline_metadata_requires_python() {
+    let dir = standalone_scripts("script_metadata_old");
+    let output = Command::new(RPY_EXE)
+        .current_dir(&dir)
+        .arg("old.py")
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
-    assert_eq!(stderr, "");
+    assert!(
+        stderr.starts_with(&format!(
+            "[rpy] Error: {dir}/bin/python is python 3.12.1, which does not satisfy \
+             requires-python '>=3.13' from {dir}/old.py",
+            dir = dir.display()
+        )),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_still_need_config_without_inline_metadata() {
+    let dir = standalone_scripts("script_metadata_plain");
+    let output = Command::new(RPY_EXE)
+        .current_dir(&dir)
+        .arg("plain.py")
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(&format!(
+            "[rpy] Error: Unable to find pyproject.toml, rpy.toml or .rpy.toml from {}",
+            dir.display()
+        )),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_provision_cached_script_env() {
+    let dir = standalone_scripts("script_metadata_env");
+    let cache = dir.join("cache");
+    let bin = Path::new(SRC_ROOT).join("test_data/env_manager/bin");
+    let run = || {
+        Command::new(RPY_EXE)
+            .current_dir(&dir)
+            .arg("env.py")
+            .env(
+                "PATH",
+                format!("{}:{}", bin.display(), env::var("PATH").unwrap()),
+            )
+            .env("XDG_CACHE_HOME", &cache)
+            .env("RPY_TEST_ENV_LOG", dir.join("env.log"))
+            .env_remove("RPY_PROFILE")
+            .env_remove("RPY_INTERPRETER")
+            .output()
+            .unwrap()
+    };
+    let output = Command::new(RPY_EXE)
+        .current_dir(&dir)
+        .args(["--rpy-explain", "env.py"])
+        .env("XDG_CACHE_HOME", &cache)
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
     assert_eq!(output.status.code().unwrap(), 0);
+    assert!(!cache.exists(), "--rpy-explain wrote to the cache");
+    for _ in 0..2 {
+        let output = run();
+        assert_eq!(
+            std::str::from_utf8(&output.stdout).unwrap(),
+            "in script env\n"
+        );
+        assert_eq!(output.status.code().unwrap(), 0);
+    }
+    let scripts = std::fs::read_dir(cache.join("rpy/scripts"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(scripts.len(), 1);
+    let env_dir = &scripts[0];
     assert_eq!(
-        stdout,
-        "interp2\n".to_string()
-            + &Path::new(SRC_ROOT)
+        std::fs::read_to_string(env_dir.join("requirements.txt")).unwrap(),
+        "requests<3\nrich\n"
+    );
+    assert!(env_dir.join(".rpy-pre-run.lock").exists());
+    assert!(env_dir.join("pre_run").is_dir());
+    assert!(!dir.join(".rpy-pre-run.lock").exists());
+    assert!(!cache.join("rpy/pre_run").exists());
+    let log = std::fs::read_to_string(dir.join("env.log")).unwrap();
+    let env_dir = env_dir.display();
+    assert_eq!(
+        log,
+        format!(
+            "uv venv --allow-existing --quiet {env_dir}/env\n\
+             uv pip sync --quiet --python {env_dir}/env/bin/python {env_dir}/requirements.txt\n"
+        )
+    );
+}
+
+#[test]
+fn should_layer_rpy_toml_files_over_pyproject_toml() {
+    let root = Path::new(SRC_ROOT).join("test_data/rpy_toml");
+    let output = Command::new(RPY_EXE)
+        .current_dir(&root)
+        .arg("env.sh")
+        .env("RPY_VERBOSE", "1")
+        .env("RPY_PROFILE", "dev")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let root = root.display();
+    assert!(
+        stdout.starts_with(&format!(
+            "project root: {root}\nconfig: {root}/pyproject.toml\nconfig: {root}/rpy.toml\n\
+             config: {root}/.rpy.toml\n"
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with("\npyproject rpy .rpy.toml pyproject dev rpy.toml dev\n"),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_work_with_only_rpy_toml() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/rpy_toml_only"))
+        .arg("env.sh")
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "only rpy.toml\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_explain_without_running_anything() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
+        .args(["--rpy-explain", "badger.sh", "--flag"])
+        .env("RPY_PROFILE", "failing")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let root = SRC_ROOT.to_string() + "/test_data/pre_run_steps";
+    assert_eq!(stderr, "");
+    assert!(
+        stdout.starts_with(&format!(
+            "config: {root}/pyproject.toml\n\
+             project root: {root}\n\
+             profile: failing\n\
+             python: bash (from tool.rpy.interpreter)\n\
+             argv: bash badger.sh --flag\n"
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("\nenv: PYTHONPATH={root}/\n")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(
+            "\npre_run: first (would run): echo first\n\
+             pre_run: broken (would run): exit 3\n\
+             supervised: no\n"
+        ),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_explain_as_json() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/supervise"))
+        .args(["--rpy-explain=json", "exit.sh", "3"])
+        .env_remove("RPY_PROFILE")
+        .env("RPY_VERBOSE", "1")
+        .output()
+        .unwrap();
+    let explanation: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let root = SRC_ROOT.to_string() + "/test_data/supervise";
+    assert_eq!(explanation["project_root"], root.as_str());
+    assert_eq!(
+        explanation["config_files"],
+        serde_json::json!([format!("{root}/pyproject.toml")])
+    );
+    assert_eq!(
+        explanation["argv"],
+        serde_json::json!(["bash", "exit.sh", "3"])
+    );
+    assert_eq!(explanation["environment"]["PYTHONSAFEPATH"], "1");
+    assert!(explanation["environment"]["RPY_PROFILE"].is_null());
+    assert_eq!(explanation["post_run"][0]["name"], "report");
+    assert_eq!(explanation["supervise"], true);
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_explain_interpreter_that_pre_run_has_still_to_create() {
+    let root = SRC_ROOT.to_string() + "/test_data/fresh_checkout";
+    let output = Command::new(RPY_EXE)
+        .current_dir(&root)
+        .args(["--rpy-explain=json", "badger.sh"])
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
+    let explanation: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(explanation["interpreter"].is_null(), "{explanation}");
+    assert!(explanation["argv"].is_null(), "{explanation}");
+    assert_eq!(explanation["args"], serde_json::json!(["badger.sh"]));
+    assert_eq!(
+        explanation["interpreter_candidates"],
+        serde_json::json!(["env/bin/python"])
+    );
+    assert!(
+        explanation["interpreter_error"]
+            .as_str()
+            .unwrap()
+            .starts_with("None of the configured interpreters could be used"),
+        "{explanation}"
+    );
+    assert_eq!(explanation["pre_run"][0]["will_run"], true);
+    assert_eq!(
+        explanation["environment"]["PYTHONPATH"],
+        format!("{root}/").as_str()
+    );
+    assert_eq!(explanation["bin_path"], format!("{root}/env/bin").as_str());
+    assert!(
+        explanation["bin_path_error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unable to canonicalize"),
+        "{explanation}"
+    );
+    assert!(
+        explanation["environment"]["PATH"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("{root}/env/bin:")),
+        "{explanation}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(&root)
+        .args(["--rpy-explain", "badger.sh"])
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.contains(
+            "\npython: not usable until pre_run has run (None of the configured interpreters"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("\npython candidates: env/bin/python\nbin_path: "),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "\nbin_path: {root}/env/bin (not usable until pre_run has run: Unable to canonicalize"
+        )),
+        "{stdout}"
+    );
+    assert!(stdout.contains("\nargv: <python> badger.sh\n"), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(&root)
+        .args(["--rpy-explain", "badger.sh"])
+        .env("RPY_PROFILE", "no_pre_run")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("[rpy] Error: None of the configured interpreters could be used"),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_keep_verbose_output_out_of_explain_json() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/simple"))
+        .args(["--rpy-verbose", "--rpy-explain=json", "--help"])
+        .output()
+        .unwrap();
+    let explanation: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(explanation["argv"], serde_json::json!(["bash", "--help"]));
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_print_activation_for_bash() {
+    let script = format!(
+        "export PYTHONPATH=/before\n\
+         unset PYTHONSAFEPATH\n\
+         eval \"$({RPY_EXE} --rpy-env=bash)\"\n\
+         echo \"$PYTHONPATH|${{PYTHONSAFEPATH-unset}}|$PATH|$RPY_IGNORE_REQUIRES_PYTHON\"\n\
+         rpy_deactivate\n\
+         echo \"$PYTHONPATH|${{PYTHONSAFEPATH-unset}}|$PATH\"\n\
+         type rpy_deactivate >/dev/null 2>&1 || echo deactivated\n"
+    );
+    let output = Command::new("bash")
+        .current_dir(Path::new(SRC_ROOT).join("test_data/bin_path"))
+        .args(["-c", &script])
+        .env("RPY_IGNORE_REQUIRES_PYTHON", "1")
+        .env("RPY_VERBOSE", "1")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let root = SRC_ROOT.to_string() + "/test_data/bin_path";
+    let path = env::var("PATH").unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        format!(
+            "{root}/|1|{root}/bin:{path}|1\n\
+             /before|unset|{path}\n\
+             deactivated\n"
+        )
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_print_activation_for_fish() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/isolation"))
+        .arg("--rpy-env=fish")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let root = SRC_ROOT.to_string() + "/test_data/isolation";
+    assert!(
+        stdout.contains(&format!("\nset -gx PYTHONPATH '{root}/'\n")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with("    functions -e rpy_deactivate\nend\n"),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_start_shell_with_project_environment() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/bin_path"))
+        .arg("--rpy-shell")
+        .env("SHELL", "/usr/bin/env")
+        .env("RPY_IGNORE_REQUIRES_PYTHON", "1")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let root = SRC_ROOT.to_string() + "/test_data/bin_path";
+    let path = env::var("PATH").unwrap();
+    assert_eq!(stderr, "");
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(
+        lines.contains(&format!("RPY_SHELL={root}").as_str()),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&format!("PYTHONPATH={root}/").as_str()),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&format!("PATH={root}/bin:{path}").as_str()),
+        "{stdout}"
+    );
+    assert!(lines.contains(&"RPY_IGNORE_REQUIRES_PYTHON=1"), "{stdout}");
+    assert!(!stdout.contains("VIRTUAL_ENV="), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_refuse_python_args_when_starting_shell() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/simple"))
+        .args(["--rpy-shell", "-m", "app"])
+        .env("SHELL", "/usr/bin/env")
+        .output()
+        .unwrap();
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: --rpy-shell starts a shell rather than python, so takes no python \
+             arguments (got '-m app')\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_activate_virtual_environment_in_shell() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/discover_venv"))
+        .arg("--rpy-shell")
+        .env("SHELL", "/usr/bin/env")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let venv = SRC_ROOT.to_string() + "/test_data/discover_venv/.venv";
+    let path = env::var("PATH").unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(
+        lines.contains(&format!("VIRTUAL_ENV={venv}").as_str()),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&format!("PATH={venv}/bin:{path}").as_str()),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_leave_python_args_after_rpy_options_untouched() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/pre_run_steps"))
+        .args([
+            "--rpy-skip-pre-run",
+            "--rpy-set",
+            "STEP_VAR=from set",
+            "-c",
+            "printf '%s|' \"$0\" \"$@\"; echo \"$STEP_VAR\"",
+            "--rpy-verbose",
+            "--",
+            "--rpy-set=A=1",
+        ])
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "--rpy-verbose|--|--rpy-set=A=1|from set\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_take_verbose_and_interpreter_from_rpy_options() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/simple"))
+        .args([
+            "--rpy-verbose",
+            "--rpy-interpreter",
+            "sh",
+            "-c",
+            "echo $0",
+            "-i",
+        ])
+        .env("RPY_INTERPRETER", "bash")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.contains("\npython: sh (from --rpy-interpreter)\n"),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("\n-i\n"), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_find_config_from_rpy_project_option() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data"))
+        .args(["--rpy-project", "env", "-c", "echo $OMP_NUM_THREADS"])
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "4\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data"))
+        .args(["--rpy-project", "missing", "-c", "true"])
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("[rpy] Error: Unable to find project directory missing\n"),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_print_help() {
+    let output = Command::new(RPY_EXE).arg("--rpy-help").output().unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("usage: rpy [rpy options] [python options and arguments]\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("\n  --rpy-set KEY=VALUE "), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+fn inspect_command(path: &str) -> Command {
+    let mut command = Command::new(RPY_EXE);
+    command
+        .current_dir(Path::new(SRC_ROOT).join("test_data"))
+        .args(["--rpy-inspect", path])
+        .env_remove("RPY_PROFILE")
+        .env_remove("RPY_INTERPRETER");
+    command
+}
+
+fn inspect(path: &str) -> serde_json::Value {
+    inspect_with(&mut inspect_command(path))
+}
+
+fn inspect_with(command: &mut Command) -> serde_json::Value {
+    let output = command.output().unwrap();
+    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+    assert_eq!(output.status.code().unwrap(), 0);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn should_inspect_file_with_its_overrides() {
+    let root = SRC_ROOT.to_string() + "/test_data/overrides";
+    let inspection = inspect("overrides/tools/badger.sh");
+    assert_eq!(inspection["schema_version"], 1);
+    assert_eq!(
+        inspection["path"],
+        format!("{root}/tools/badger.sh").as_str()
+    );
+    assert_eq!(inspection["project_root"], root.as_str());
+    assert_eq!(
+        inspection["interpreter"]["raw"],
+        format!("{root}/bin/interp-tools").as_str()
+    );
+    assert_eq!(
+        inspection["interpreter"]["canonical"],
+        format!("{root}/bin/interp-tools").as_str()
+    );
+    assert_eq!(
+        inspection["source_roots"],
+        serde_json::json!([format!("{root}/")])
+    );
+    assert_eq!(inspection["bin_path"], serde_json::Value::Null);
+    assert_eq!(
+        inspection["environment"]["PYTHONPATH"],
+        format!("{root}/").as_str()
+    );
+
+    let inspection = inspect("overrides/src/badger.sh");
+    assert_eq!(
+        inspection["interpreter"]["raw"],
+        format!("{root}/bin/interp").as_str()
+    );
+}
+
+#[test]
+fn should_inspect_directory() {
+    let root = SRC_ROOT.to_string() + "/test_data/pre_run_steps";
+    let inspection = inspect("pre_run_steps/sub");
+    assert_eq!(inspection["path"], format!("{root}/sub").as_str());
+    assert_eq!(inspection["project_root"], root.as_str());
+    assert_eq!(
+        inspection["config_files"],
+        serde_json::json!([format!("{root}/pyproject.toml")])
+    );
+    assert_eq!(inspection["interpreter"]["raw"], "bash");
+    assert_eq!(inspection["interpreter"]["source"], "tool.rpy.interpreter");
+    assert_eq!(
+        inspection["pre_run"],
+        serde_json::json!([
+            {"name": "first", "cmd": "echo \"first $PWD $STEP_VAR\"", "will_run": true},
+            {"name": "pre_run[1]", "cmd": "echo second", "will_run": true},
+        ])
+    );
+}
+
+#[test]
+fn should_inspect_project_whose_interpreter_does_not_exist_yet() {
+    let root = SRC_ROOT.to_string() + "/test_data/fresh_checkout";
+    let inspection = inspect("fresh_checkout/badger.sh");
+    assert_eq!(inspection["project_root"], root.as_str());
+    assert_eq!(
+        inspection["source_roots"],
+        serde_json::json!([format!("{root}/")])
+    );
+    let interpreter = &inspection["interpreter"];
+    assert!(interpreter["raw"].is_null(), "{interpreter}");
+    assert!(interpreter["canonical"].is_null(), "{interpreter}");
+    assert_eq!(
+        interpreter["candidates"],
+        serde_json::json!(["env/bin/python"])
+    );
+    assert_eq!(
+        interpreter["error"],
+        format!(
+            "None of the configured interpreters could be used:\n  \
+             env/bin/python: {root}/env/bin/python does not exist"
+        )
+        .as_str()
+    );
+    assert_eq!(inspection["pre_run"][0]["will_run"], true);
+}
+
+#[test]
+fn should_inspect_project_whose_bin_path_does_not_exist_yet() {
+    let root = SRC_ROOT.to_string() + "/test_data/fresh_checkout";
+    let inspection = inspect("fresh_checkout/badger.sh");
+    assert_eq!(inspection["bin_path"], format!("{root}/env/bin").as_str());
+    assert!(
+        inspection["bin_path_error"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("Unable to canonicalize {root}/env/bin: ")),
+        "{inspection}"
+    );
+    assert!(
+        inspection["environment"]["PATH"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("{root}/env/bin:")),
+        "{inspection}"
+    );
+
+    let inspection = inspect("bin_path_interpreter/tools/mypython");
+    assert_eq!(
+        inspection["bin_path"],
+        format!("{SRC_ROOT}/test_data/bin_path_interpreter/tools").as_str()
+    );
+    assert!(inspection["bin_path_error"].is_null(), "{inspection}");
+}
+
+#[test]
+fn should_inspect_interpreter_not_matching_requires_python() {
+    let inspection = inspect_with(
+        inspect_command("requires_python/badger.sh")
+            .env("RPY_PROFILE", "old")
+            .env(
+                "XDG_CACHE_HOME",
+                fresh_cache_dir("requires_python_inspect_cache"),
+            ),
+    );
+    let interpreter = &inspection["interpreter"];
+    assert_eq!(
+        interpreter["raw"],
+        format!("{SRC_ROOT}/test_data/requires_python/bin/python-old").as_str()
+    );
+    assert_eq!(interpreter["version"], "3.9.18");
+    assert_eq!(interpreter["requires_python"], ">=3.10");
+    assert_eq!(interpreter["satisfies_requires_python"], false);
+    assert!(interpreter["error"].is_null(), "{interpreter}");
+}
+
+#[test]
+fn should_refuse_python_args_when_inspecting() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/simple"))
+        .args(["--rpy-inspect", "badger.sh", "-v"])
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: --rpy-inspect describes a path, so takes no python arguments (got '-v')\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_work_with_source_root_pyproject_toml() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/source_root"))
+        .arg("badger.sh")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        "badger\n".to_string() + SRC_ROOT + "/test_data/source_root/src\n"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_work_with_multiple_source_roots() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/source_roots"))
+        .arg("badger.sh")
+        .env_remove("PYTHONPATH")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    let root = SRC_ROOT.to_string() + "/test_data/source_roots";
+    assert_eq!(
+        stdout,
+        format!("badger\n{root}/src/py:{root}/gen/py:{root}/vendor/py\n")
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_append_inherited_pythonpath_after_source_roots() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/source_roots"))
+        .arg("badger.sh")
+        .env("PYTHONPATH", "/inherited/one:/inherited/two")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    let root = SRC_ROOT.to_string() + "/test_data/source_roots";
+    assert_eq!(
+        stdout,
+        format!(
+            "badger\n{root}/src/py:{root}/gen/py:{root}/vendor/py:/inherited/one:/inherited/two\n"
+        )
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_work_with_bin_path_pyproject_toml() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/bin_path"))
+        .arg("badger.sh")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(
+        stdout,
+        "badger\n".to_string()
+            + SRC_ROOT
+            + "/test_data/bin_path/bin:"
+            + &env::var("PATH").unwrap()
+            + "\n"
+    );
+    assert_eq!(stderr, "");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_find_bare_interpreter_in_bin_path() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/bin_path_interpreter"))
+        .arg("badger.sh")
+        .env_remove("RPY_INTERPRETER")
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "mypython badger.sh\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_work_with_rel_interp_pyproject_toml() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/rel_interp"))
+        .arg("badger.sh")
+        .arg("foo")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "interp\nbadger.sh foo\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_work_with_rel_rpy_interpreter_environment_override() {
+    let output = Command::new(RPY_EXE)
+        .arg(Path::new(SRC_ROOT).join("test_data/rel_interp/badger.sh"))
+        .arg("foo")
+        .env("RPY_INTERPRETER", "bin/interp2")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(
+        stdout,
+        "interp2\n".to_string()
+            + &Path::new(SRC_ROOT)
                 .join("test_data/rel_interp/badger.sh")
                 .display()
                 .to_string()
-            + " foo\n"
+            + " foo\n"
+    );
+}
+
+#[test]
+fn should_work_with_abs_rpy_interpreter_environment_override() {
+    let output = Command::new(RPY_EXE)
+        .arg(Path::new(SRC_ROOT).join("test_data/rel_interp/badger.sh"))
+        .arg("foo")
+        .env("RPY_INTERPRETER", "interp2")
+        .env(
+            "PATH",
+            Path::new(SRC_ROOT)
+                .join("test_data/rel_interp/bin")
+                .display()
+                .to_string()
+                + ":"
+                + &env::var("PATH").unwrap(),
+        )
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(
+        stdout,
+        "interp2\n".to_string()
+            + &Path::new(SRC_ROOT)
+                .join("test_data/rel_interp/badger.sh")
+                .display()
+                .to_string()
+            + " foo\n"
+    );
+}
+
+#[test]
+fn should_use_base_config_without_a_profile() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/profiles"))
+        .arg("badger.sh")
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        "interp\n".to_string() + SRC_ROOT + "/test_data/profiles/src\nbadger.sh\n"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_select_profile_from_environment() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/profiles"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "debug")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        "interp-debug\n".to_string() + SRC_ROOT + "/test_data/profiles/src\nbadger.sh\n"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_select_profile_from_rpy_option_over_environment() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/profiles"))
+        .args([
+            "--rpy-profile",
+            "compat",
+            "badger.sh",
+            "--rpy-profile=debug",
+        ])
+        .env("RPY_PROFILE", "debug")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    let root = SRC_ROOT.to_string() + "/test_data/profiles";
+    assert_eq!(
+        stdout,
+        format!("interp\n{root}/src:{root}/compat\nbadger.sh --rpy-profile=debug\n")
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_report_profile_when_verbose() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/profiles"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "debug")
+        .env("RPY_VERBOSE", "1")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(stdout.contains("\nprofile: debug\n"), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_fail_with_unknown_profile() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/profiles"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "prod")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.starts_with("[rpy] Error: Unknown profile 'prod' (available: compat, debug)\n"),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_apply_overrides_matching_script_path() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/overrides/src"))
+        .arg("../tools/badger.sh")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "interp-tools\n../tools/badger.sh\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/overrides/tools"))
+        .arg("../src/badger.sh")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout, "interp\n../src/badger.sh\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_apply_overrides_matching_module_prefix() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/overrides"))
+        .args(["-m", "tools.badger"])
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "interp-tools\n-m tools.badger\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/overrides"))
+        .args(["-m", "toolshed"])
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout, "interp\n-m toolshed\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_apply_env_table() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/env"))
+        .arg("badger.sh")
+        .env("RPY_TEST_INHERITED", "outside")
+        .env("RPY_TEST_PATH", "/inherited")
+        .env("RPY_TEST_REMOVED", "still here")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    let root = SRC_ROOT.to_string() + "/test_data/env";
+    assert_eq!(
+        stdout,
+        format!(
+            "badger\n4\n{root}/data\n{root}/src\nfrom outside\n\
+             {root}/first:/inherited:{root}/last:/opt/last\nremoved\n"
+        )
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_allow_isolation_variables_to_be_disabled() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/isolation"))
+        .arg("badger.sh")
+        .env_remove("PYTHONNOUSERSITE")
+        .env_remove("PYTHONSAFEPATH")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "badger\nunset\n1\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/isolation"))
+        .arg("legacy/badger.sh")
+        .env_remove("PYTHONNOUSERSITE")
+        .env_remove("PYTHONSAFEPATH")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout, "badger\nunset\nunset\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_unset_inherited_isolation_variables_when_disabled() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/isolation"))
+        .arg("legacy/badger.sh")
+        .env("PYTHONNOUSERSITE", "1")
+        .env("PYTHONSAFEPATH", "1")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "badger\nunset\nunset\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_report_isolation_variables_when_verbose() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/isolation"))
+        .arg("badger.sh")
+        .env("RPY_VERBOSE", "1")
+        .env_remove("PYTHONNOUSERSITE")
+        .env_remove("PYTHONSAFEPATH")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.contains("\nPYTHONNOUSERSITE: not set\nPYTHONSAFEPATH: 1\n"),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_load_dotenv_files_under_rpy_settings() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/dotenv"))
+        .arg("badger.sh")
+        .env("RPY_TEST_A", "inherited")
+        .env("PYTHONPATH", "/inherited")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        "badger\n".to_string()
+            + SRC_ROOT
+            + "/test_data/dotenv/src:/from/dotenv\n1\nfrom dotenv\nfrom pyproject\n"
+    );
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_fail_with_malformed_dotenv_file() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/dotenv_malformed"))
+        .arg("badger.sh")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.starts_with(
+            &("[rpy] Error: ".to_string()
+                + SRC_ROOT
+                + "/test_data/dotenv_malformed/.env:2: expected KEY=VALUE\n")
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_use_first_usable_interpreter_candidate() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/interp_candidates"))
+        .arg("badger.sh")
+        .env("RPY_VERBOSE", "1")
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert!(
+        stdout.contains(
+            &("\npython: ".to_string()
+                + SRC_ROOT
+                + "/test_data/interp_candidates/bin/interp (from tool.rpy.interpreter[2])\n")
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("\ninterp\nbadger.sh\n"), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_list_every_rejected_interpreter_candidate() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/interp_candidates"))
+        .arg("badger.sh")
+        .env("RPY_PROFILE", "broken")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "");
+    let root = SRC_ROOT.to_string() + "/test_data/interp_candidates";
+    assert!(
+        stderr.starts_with(&format!(
+            "[rpy] Error: None of the configured interpreters could be used:\n\
+             \x20 out/env/bin/python: {root}/out/env/bin/python does not exist\n\
+             \x20 bin/not-executable: {root}/bin/not-executable is not executable\n\
+             \x20 rpy-no-such-python: not found on the PATH\n"
+        )),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_discover_project_venv_interpreter() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/discover_venv"))
+        .arg("badger.sh")
+        .env("RPY_VERBOSE", "1")
+        .env_remove("RPY_INTERPRETER")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert!(
+        stdout.contains(
+            &("\npython: ".to_string()
+                + SRC_ROOT
+                + "/test_data/discover_venv/.venv/bin/python (from .venv/bin/python)\n")
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("\nvenv python\nbadger.sh\n"), "{stdout}");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_discover_interpreter_from_requires_python() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/discover_requires"))
+        .arg("badger.sh")
+        .env("XDG_CACHE_HOME", fresh_cache_dir("discover_requires_cache"))
+        .env_remove("VIRTUAL_ENV")
+        .env(
+            "PATH",
+            Path::new(SRC_ROOT)
+                .join("test_data/discover_requires/bin")
+                .display()
+                .to_string()
+                + ":"
+                + &env::var("PATH").unwrap(),
+        )
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "python3.99\nbadger.sh\n");
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_fail_when_no_interpreter_can_be_discovered() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/discover_none"))
+        .arg("badger.sh")
+        .env_remove("VIRTUAL_ENV")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.starts_with(
+            "[rpy] Error: No interpreter configured in [tool.rpy] and none could be discovered"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+}
+
+#[test]
+fn should_check_and_cache_interpreter_version_against_requires_python() {
+    let cache_dir = fresh_cache_dir("requires_python_cache");
+    let query_log = cache_dir.with_extension("log");
+    std::fs::remove_file(&query_log).ok();
+    for _ in 0..2 {
+        let output = Command::new(RPY_EXE)
+            .current_dir(Path::new(SRC_ROOT).join("test_data/requires_python"))
+            .arg("badger.sh")
+            .env("XDG_CACHE_HOME", &cache_dir)
+            .env("RPY_TEST_QUERY_LOG", &query_log)
+            .env_remove("RPY_PROFILE")
+            .output()
+            .unwrap();
+        let stdout = std::str::from_utf8(&output.stdout).unwrap();
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert_eq!(stderr, "");
+        assert_eq!(stdout, "python-new\nbadger.sh\n");
+        assert_eq!(output.status.code().unwrap(), 0);
+    }
+    assert_eq!(std::fs::read_to_string(&query_log).unwrap(), "queried\n");
+}
+
+#[test]
+fn should_list_and_clear_interpreter_cache() {
+    let cache_dir = fresh_cache_dir("cache_command_cache");
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/requires_python"))
+        .arg("badger.sh")
+        .env("XDG_CACHE_HOME", &cache_dir)
+        .env_remove("RPY_PROFILE")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .args(["--rpy-cache", "list"])
+        .env("XDG_CACHE_HOME", &cache_dir)
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr, "");
+    assert_eq!(
+        stdout,
+        format!(
+            "cache: {}/rpy\n{SRC_ROOT}/test_data/requires_python/bin/python-new\n\
+             \x20 version: 3.11.7\n  prefix: /opt/badger\n  platform: linux-x86_64\n",
+            cache_dir.display()
+        )
     );
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = Command::new(RPY_EXE)
+        .arg("--rpy-cache=clear")
+        .env("XDG_CACHE_HOME", &cache_dir)
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout, format!("cleared: {}/rpy\n", cache_dir.display()));
+    assert!(!cache_dir.join("rpy").exists());
+    assert_eq!(output.status.code().unwrap(), 0);
+}
+
+#[test]
+fn should_refuse_interpreter_not_matching_requires_python() {
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/requires_python"))
+        .arg("badger.sh")
+        .env(
+            "XDG_CACHE_HOME",
+            fresh_cache_dir("requires_python_old_cache"),
+        )
+        .env("RPY_PROFILE", "old")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stdout, "");
+    let root = SRC_ROOT.to_string() + "/test_data/requires_python";
+    assert!(
+        stderr.starts_with(&format!(
+            "[rpy] Error: {root}/bin/python-old is python 3.9.18, which does not satisfy \
+             requires-python '>=3.10' from {root}/pyproject.toml \
+             (set RPY_IGNORE_REQUIRES_PYTHON=1 to run it anyway)\n"
+        )),
+        "{stderr}"
+    );
+    assert_eq!(output.status.code().unwrap(), 1);
+
+    let output = Command::new(RPY_EXE)
+        .current_dir(Path::new(SRC_ROOT).join("test_data/requires_python"))
+        .arg("badger.sh")
+        .env(
+            "XDG_CACHE_HOME",
+            fresh_cache_dir("requires_python_old_cache"),
+        )
+        .env("RPY_PROFILE", "old")
+        .env("RPY_IGNORE_REQUIRES_PYTHON", "1")
+        .output()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout, "python-old\nbadger.sh\n");
+    assert_eq!(output.status.code().unwrap(), 0);
 }
 
 #[test]